@@ -0,0 +1,23 @@
+use crate::error::ApiError;
+use serde_json::{Map, Value};
+
+/// The row under `tag`, as an object of column values.
+pub fn body_object<'a>(body: &'a Value, tag: &str) -> Result<&'a Map<String, Value>, ApiError> {
+    match body.get(tag).and_then(Value::as_object) {
+        Some(object) if !object.is_empty() => Ok(object),
+        _ => Err(ApiError::missing_field(tag, "an object of column values")),
+    }
+}
+
+/// The rows of an `insert_many` body, given as a `values` array of objects.
+pub fn body_rows(body: &Value) -> Result<Vec<&Map<String, Value>>, ApiError> {
+    body.get("values")
+        .and_then(Value::as_array)
+        .ok_or_else(|| ApiError::missing_field("values", "the rows to be inserted in the table"))?
+        .iter()
+        .map(Value::as_object)
+        .collect::<Option<_>>()
+        .ok_or_else(|| {
+            ApiError::BadRequest(String::from("Every entry in `values` should be an object"))
+        })
+}
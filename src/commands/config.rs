@@ -0,0 +1,496 @@
+use super::init::{ConfigLayout, ConnectionConfig, Database, DatabaseKind, KeyConfig, RuleConfig};
+use crate::{logging, middleware::access::Role, mongo::expose::Expose};
+use actix_web::http::header::HeaderName;
+use serde_yaml::Value;
+use std::{
+    collections::{HashMap, HashSet},
+    env, fs, io,
+    ops::Range,
+    path::Path,
+};
+
+/// Expands `${NAME}` to the value of the environment variable `NAME`, or to
+/// `default` for `${NAME:-default}` when it is unset. `$${` is kept as a
+/// literal `${`. `line` is only used to point errors at the config file.
+fn expand(value: &str, line: impl Fn(&str) -> Option<usize>) -> Result<String, String> {
+    let at = |expression: &str, message: String| match line(expression) {
+        Some(line) => format!("line {line}: {message}"),
+        None => message,
+    };
+    let mut output: String = String::with_capacity(value.len());
+    let mut rest: &str = value;
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            output.push_str(&rest[..start - 1]);
+            output.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        output.push_str(&rest[..start]);
+        let Some(length) = rest[start..].find('}') else {
+            return Err(at(&rest[start..], String::from("`${` is never closed")));
+        };
+        let expression: &str = &rest[start + 2..start + length];
+        let (name, default) = match expression.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (expression, None),
+        };
+        match (env::var(name), default) {
+            (Ok(value), _) => output.push_str(&value),
+            (Err(_), Some(default)) => output.push_str(default),
+            (Err(_), None) => {
+                return Err(at(
+                    &rest[start..start + length + 1],
+                    format!("environment variable `{name}` is not set"),
+                ))
+            }
+        }
+        rest = &rest[start + length + 1..];
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+/// True for values that read the same as any YAML type once written
+/// unquoted, such as `8080`, `true` or `postgres`, and cannot start a
+/// comment, a mapping or a new line.
+fn is_plain_word(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '+' | '-'))
+}
+
+/// Where the value of `key: ${NAME}` or `- ${NAME}` is written, when the
+/// whole unquoted value is a single expression.
+fn scalar_expression(line: &str) -> Option<Range<usize>> {
+    if !Lines::is_content(line) {
+        return None;
+    }
+    let value_start: usize = match line.find(": ") {
+        Some(colon) => colon + 2,
+        None if line.trim_start().starts_with("- ") => line.len() - line.trim_start().len() + 2,
+        None => return None,
+    };
+    let start: usize = value_start + line[value_start..].find(|c: char| c != ' ')?;
+    let value: &str = &line[start..];
+    let value: &str = value.split(" #").next().unwrap_or_default().trim_end();
+    let is_single: bool = value.starts_with("${") && value.find('}') == Some(value.len() - 1);
+    is_single.then_some(start..start + value.len())
+}
+
+/// Expands the unquoted values that are a single `${NAME}`, e.g.
+/// `port: ${PORT}`, before the YAML is parsed so that they take the type of
+/// their field. Only plain words are written into the text, anything else
+/// is left for `interpolate`, which sees it as a string.
+fn expand_scalars(text: &str) -> Result<String, String> {
+    let mut output: String = String::with_capacity(text.len());
+    for (index, line) in text.split_inclusive('\n').enumerate() {
+        let Some(range) = scalar_expression(line.trim_end_matches('\n')) else {
+            output.push_str(line);
+            continue;
+        };
+        let expanded: String = expand(&line[range.clone()], |_| Some(index + 1))?;
+        if is_plain_word(&expanded) {
+            output.push_str(&line[..range.start]);
+            output.push_str(&expanded);
+            output.push_str(&line[range.end..]);
+        } else {
+            output.push_str(line);
+        }
+    }
+    Ok(output)
+}
+
+/// Expands `${NAME}` in every string of the parsed config. Values are never
+/// written into the YAML text, so quotes, `#` or new lines in them cannot
+/// change its structure, and comments are not expanded at all.
+fn interpolate(config: ConfigLayout, text: &str) -> Result<ConfigLayout, String> {
+    let lines: Lines = Lines::new(text);
+    let line = |expression: &str| -> Option<usize> {
+        lines
+            .0
+            .iter()
+            .position(|line| Lines::is_content(line) && line.contains(expression))
+            .map(|index| index + 1)
+    };
+    let mut value: Value = serde_yaml::to_value(&config).map_err(|e| e.to_string())?;
+    expand_strings(&mut value, &line)?;
+    serde_yaml::from_value(value).map_err(|e| e.to_string())
+}
+
+fn expand_strings(value: &mut Value, line: &dyn Fn(&str) -> Option<usize>) -> Result<(), String> {
+    match value {
+        Value::String(text) if text.contains("${") => *text = expand(text, line)?,
+        Value::Sequence(values) => {
+            for value in values.iter_mut() {
+                expand_strings(value, line)?;
+            }
+        }
+        Value::Mapping(mapping) => {
+            for (_, value) in mapping.iter_mut() {
+                expand_strings(value, line)?;
+            }
+        }
+        Value::Tagged(tagged) => expand_strings(&mut tagged.value, line)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Finds where a key is written so that errors can point at its line.
+struct Lines<'a>(Vec<&'a str>);
+
+impl<'a> Lines<'a> {
+    fn new(text: &'a str) -> Lines<'a> {
+        Lines(text.lines().collect())
+    }
+
+    fn is_content(line: &str) -> bool {
+        let line: &str = line.trim_start();
+        !line.is_empty() && !line.starts_with('#')
+    }
+
+    fn is_top_level(line: &str) -> bool {
+        Lines::is_content(line) && !line.starts_with(' ') && !line.starts_with('-')
+    }
+
+    /// Line number of the first `key:` in `range`, at any indentation.
+    fn find(&self, key: &str, range: Range<usize>) -> Option<usize> {
+        let end: usize = range.end.min(self.0.len());
+        (range.start..end).find_map(|index| {
+            let line: &str = self.0[index].trim_start().trim_start_matches("- ");
+            let rest: &str = line.strip_prefix(key)?;
+            (Lines::is_content(self.0[index]) && rest.starts_with(':')).then_some(index + 1)
+        })
+    }
+
+    fn top_level(&self, key: &str) -> Option<usize> {
+        (0..self.0.len()).find_map(|index| {
+            let line: &str = self.0[index];
+            let rest: &str = line.strip_prefix(key)?;
+            (Lines::is_top_level(line) && rest.starts_with(':')).then_some(index + 1)
+        })
+    }
+
+    /// The lines of the block below a top-level key, e.g. `tls:`.
+    fn block(&self, key: &str) -> Range<usize> {
+        let Some(start) = self.top_level(key) else {
+            return 0..0;
+        };
+        let end: usize = (start..self.0.len())
+            .find(|index| Lines::is_top_level(self.0[*index]))
+            .unwrap_or(self.0.len());
+        start - 1..end
+    }
+
+    /// The lines of the `index`th entry of a top-level list.
+    fn entry(&self, list: &str, index: usize) -> Range<usize> {
+        let block: Range<usize> = self.block(list);
+        let starts: Vec<usize> = block
+            .clone()
+            .skip(1)
+            .filter(|line| self.0[*line].trim_start().starts_with('-'))
+            .collect();
+        match starts.get(index) {
+            Some(start) => *start..starts.get(index + 1).copied().unwrap_or(block.end),
+            None => 0..0,
+        }
+    }
+}
+
+fn uri_error(connection: &ConnectionConfig) -> Option<String> {
+    let uri: &str = connection.uri.trim();
+    if uri.is_empty() {
+        return Some(format!("uri is empty for {}", connection.database));
+    }
+    let (valid, expected): (bool, &str) = match connection.database {
+        DatabaseKind::Mongodb => (
+            uri.starts_with("mongodb://") || uri.starts_with("mongodb+srv://"),
+            "mongodb:// or mongodb+srv://",
+        ),
+        DatabaseKind::Postgres => (
+            uri.starts_with("postgres://")
+                || uri.starts_with("postgresql://")
+                || (!uri.contains("://") && uri.contains('=')),
+            "postgres://, postgresql:// or key=value pairs",
+        ),
+        DatabaseKind::MySQL => (uri.starts_with("mysql://"), "mysql://"),
+    };
+    match valid {
+        true => None,
+        false => Some(format!(
+            "uri for {} must start with {expected}",
+            connection.database
+        )),
+    }
+}
+
+/// Checks everything that can be checked without opening a connection and
+/// returns every problem found, each prefixed with its line when known.
+pub fn validate(config: &ConfigLayout, text: &str) -> Vec<String> {
+    let lines: Lines = Lines::new(text);
+    let mut errors: Vec<(Option<usize>, String)> = Vec::new();
+    let mut error = |line: Option<usize>, message: String| errors.push((line, message));
+
+    let listed: usize = config.connections.as_ref().map_or(0, Vec::len);
+    let connections: Vec<ConnectionConfig> = config.connections();
+    if connections.is_empty() {
+        error(
+            lines.top_level("database"),
+            String::from("no database configured, set `database` or `connections`"),
+        );
+    }
+    if config.database.is_none() && !config.uri.is_empty() {
+        error(
+            lines.top_level("uri"),
+            String::from("uri is set but database is empty"),
+        );
+    }
+    let mut prefixes: HashSet<String> = HashSet::new();
+    for (index, connection) in connections.iter().enumerate() {
+        let (uri_line, name_line) = match index < listed {
+            true => {
+                let entry: Range<usize> = lines.entry("connections", index);
+                (lines.find("uri", entry.clone()), lines.find("name", entry))
+            }
+            false => (lines.top_level("uri"), lines.top_level("database")),
+        };
+        if let Some(message) = uri_error(connection) {
+            error(uri_line, message);
+        }
+        match Database::from_connection(connection).prefix(connection) {
+            Ok(prefix) if !prefixes.insert(prefix.clone()) => error(
+                name_line,
+                format!("more than one connection is mounted at {prefix}"),
+            ),
+            Ok(_) => {}
+            Err(e) => error(name_line, e.to_string()),
+        }
+        if connection.pool.as_ref().and_then(|pool| pool.max_size) == Some(0) {
+            let line: Option<usize> = match index < listed {
+                true => lines.find("max_size", lines.entry("connections", index)),
+                false => lines.find("max_size", lines.block("pool")),
+            };
+            error(line, String::from("pool max_size must be at least 1"));
+        }
+    }
+
+    let scoped_keys: &[KeyConfig] = config.api_keys.as_deref().unwrap_or_default();
+    if config.api_key.is_empty() && scoped_keys.is_empty() {
+        error(
+            lines.top_level("api_key"),
+            String::from("api_key or api_keys must be set"),
+        );
+    }
+    let roles: Option<&HashMap<String, Vec<RuleConfig>>> = config.roles.as_ref();
+    for (index, key) in scoped_keys.iter().enumerate() {
+        let entry: Range<usize> = lines.entry("api_keys", index);
+        if key.key.is_empty() {
+            error(
+                lines.find("key", entry.clone()),
+                String::from("key is empty"),
+            );
+        }
+        if !roles.is_some_and(|roles| roles.contains_key(&key.role)) {
+            error(
+                lines.find("role", entry),
+                format!("role `{}` is not defined in roles", key.role),
+            );
+        }
+    }
+    for (name, rules) in roles.into_iter().flatten() {
+        if let Err(e) = Role::from_config(name, rules) {
+            error(lines.find(name, lines.block("roles")), e);
+        }
+    }
+    if let Some(header) = config.auth_header.as_deref().map(str::trim) {
+        if !header.is_empty() && HeaderName::try_from(header).is_err() {
+            error(
+                lines.top_level("auth_header"),
+                format!("`{header}` is not a valid header name"),
+            );
+        }
+    }
+    if config.max_body_size == Some(0) {
+        error(
+            lines.top_level("max_body_size"),
+            String::from("max_body_size must be at least 1"),
+        );
+    }
+    for operator in config.allowed_operators.iter().flatten() {
+        let name: &str = operator.trim_start_matches('$');
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphabetic()) {
+            error(
+                lines.top_level("allowed_operators"),
+                format!("`{operator}` is not a query operator"),
+            );
+        }
+    }
+
+    if let Err(e) = Expose::from_config(config) {
+        error(lines.top_level("expose"), e);
+    }
+    if let Some(level) = config.log.as_ref().and_then(|log| log.level.as_deref()) {
+        if let Err(e) = logging::filter(level) {
+            error(lines.find("level", lines.block("log")), e.to_string());
+        }
+    }
+
+    let mut files: Vec<(&str, &str, &str)> = Vec::new();
+    if let Some(tls) = &config.tls {
+        files.push(("tls", "cert_path", &tls.cert_path));
+        files.push(("tls", "key_path", &tls.key_path));
+        if let Some(path) = &tls.client_ca_path {
+            files.push(("tls", "client_ca_path", path));
+        }
+    }
+    if let Some(tls) = &config.database_tls {
+        let paths = [
+            ("root_cert_path", &tls.root_cert_path),
+            ("client_cert_path", &tls.client_cert_path),
+            ("client_key_path", &tls.client_key_path),
+        ];
+        for (key, path) in paths {
+            if let Some(path) = path {
+                files.push(("database_tls", key, path));
+            }
+        }
+    }
+    for (block, key, path) in files {
+        if !Path::new(path).is_file() {
+            error(
+                lines.find(key, lines.block(block)),
+                format!("{block}.{key} `{path}` does not exist"),
+            );
+        }
+    }
+    errors.sort_by_key(|(line, _)| *line);
+    errors
+        .into_iter()
+        .map(|(line, message)| match line {
+            Some(line) => format!("line {line}: {message}"),
+            None => message,
+        })
+        .collect()
+}
+
+/// Expands the environment variables of the config text and parses it.
+fn parse(text: &str) -> Result<ConfigLayout, String> {
+    let text: String = expand_scalars(text)?;
+    let config: ConfigLayout = serde_yaml::from_str(&text).map_err(|e| e.to_string())?;
+    interpolate(config, &text)
+}
+
+/// Reads, interpolates, parses and validates the config file. Every
+/// problem is reported in the returned error, not only the first one.
+pub fn load(path: &Path) -> io::Result<ConfigLayout> {
+    let text: String = fs::read_to_string(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("Unable to read {}: {e}", path.display())))?;
+    let config: ConfigLayout = parse(&text).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {e}", path.display()),
+        )
+    })?;
+    let errors: Vec<String> = validate(&config, &text);
+    if !errors.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is invalid:\n  {}", path.display(), errors.join("\n  ")),
+        ));
+    }
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unquoted_expressions_take_the_field_type() {
+        env::set_var("SERVEUR_TEST_PORT", "9090");
+        env::set_var("SERVEUR_TEST_READ_ONLY", "true");
+        let config: ConfigLayout = parse(
+            "api_key: k\n\
+             port: ${SERVEUR_TEST_PORT} # ${SERVEUR_TEST_UNSET}\n\
+             read_only: ${SERVEUR_TEST_READ_ONLY}\n",
+        )
+        .unwrap();
+        assert_eq!(config.port, Some(9090));
+        assert_eq!(config.read_only, Some(true));
+    }
+
+    #[test]
+    fn values_cannot_change_the_structure() {
+        env::set_var("SERVEUR_TEST_KEY", "a\"\nread_only: true\n#");
+        let config: ConfigLayout = parse("api_key: \"${SERVEUR_TEST_KEY}\"\n").unwrap();
+        assert_eq!(config.api_key, "a\"\nread_only: true\n#");
+        assert_eq!(config.read_only, None);
+        let config: ConfigLayout = parse("api_key: ${SERVEUR_TEST_KEY}\n").unwrap();
+        assert_eq!(config.api_key, "a\"\nread_only: true\n#");
+    }
+
+    #[test]
+    fn strings_are_expanded_anywhere_in_the_value() {
+        env::set_var("SERVEUR_TEST_USER", "app");
+        let config: ConfigLayout = parse(
+            "api_key: \"$${literal}\"\n\
+             uri: postgres://${SERVEUR_TEST_USER}:${SERVEUR_TEST_UNSET:-secret}@db/app\n",
+        )
+        .unwrap();
+        assert_eq!(config.uri, "postgres://app:secret@db/app");
+        assert_eq!(config.api_key, "${literal}");
+    }
+
+    #[test]
+    fn comments_are_never_expanded() {
+        let config: ConfigLayout =
+            parse("# api_key: ${SERVEUR_TEST_UNSET}\napi_key: k # or ${SERVEUR_TEST_UNSET}\n")
+                .unwrap();
+        assert_eq!(config.api_key, "k");
+    }
+
+    #[test]
+    fn unset_variables_point_at_their_line() {
+        let error: String = parse("api_key: k\nport: ${SERVEUR_TEST_UNSET}\n").unwrap_err();
+        assert_eq!(
+            error,
+            "line 2: environment variable `SERVEUR_TEST_UNSET` is not set"
+        );
+        let error: String = parse("api_key: k\nhost: \"${SERVEUR_TEST_UNSET}\"\n").unwrap_err();
+        assert_eq!(
+            error,
+            "line 2: environment variable `SERVEUR_TEST_UNSET` is not set"
+        );
+        assert!(parse("api_key: \"${SERVEUR_TEST_UNSET\"\n")
+            .unwrap_err()
+            .contains("never closed"));
+    }
+
+    #[test]
+    fn lines_find_keys_in_blocks() {
+        let lines: Lines = Lines::new(concat!(
+            "# tls: commented\n",
+            "port: 1\n",
+            "connections:\n",
+            "  - database: postgres\n",
+            "    uri: a\n",
+            "  - database: mongodb\n",
+            "    uri: b\n",
+            "tls:\n",
+            "  cert_path: c\n",
+        ));
+        assert_eq!(lines.top_level("tls"), Some(8));
+        assert_eq!(lines.top_level("uri"), None);
+        assert_eq!(lines.block("connections"), 2..7);
+        assert_eq!(lines.entry("connections", 1), 5..7);
+        assert_eq!(lines.find("uri", lines.entry("connections", 1)), Some(7));
+        assert_eq!(
+            lines.find("database", lines.entry("connections", 0)),
+            Some(4)
+        );
+        assert_eq!(lines.entry("connections", 2), 0..0);
+        assert_eq!(lines.block("missing"), 0..0);
+    }
+}
@@ -1,2 +1,4 @@
+pub mod config;
 pub mod init;
-pub mod start;
\ No newline at end of file
+pub mod start;
+pub mod validate;
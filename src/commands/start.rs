@@ -1,36 +1,61 @@
-use super::init::Database;
-use serde_yaml::Value;
-use std::{env, fs, io, path::PathBuf};
+use super::{
+    config,
+    init::{run, ConfigLayout, ConnectionConfig, Database},
+};
+use crate::logging;
+use clap::Args;
+use std::{io, path::Path};
 
-pub async fn start() -> io::Result<()> {
-    let current_working_directory: PathBuf =
-        env::current_dir().expect("Unable to get current directory.");
-    let config_file_path: PathBuf = current_working_directory.join("config.yaml");
-    let file_contents: String = fs::read_to_string(config_file_path)?;
-    let config_contents: Value =
-        serde_yaml::from_str(&file_contents).expect("Unable to parse config file.");
-    match config_contents["database"].as_str() {
-        Some("Mongodb") => {
-            let database: Database = Database::Mongodb {
-                uri: config_contents["uri"].as_str().unwrap().to_string(),
-            };
-            let _a: Result<(), io::Error> = database.run().await;
-        }
-        Some("Postgres") => {
-            let database: Database = Database::Postgres {
-                uri: config_contents["uri"].as_str().unwrap().to_string(),
-            };
-            let _a: Result<(), io::Error> = database.run().await;
-        }
-        Some("MySQL") => {
-            let database: Database = Database::MySQL {
-                uri: config_contents["uri"].as_str().unwrap().to_string(),
-            };
-            let _a: Result<(), io::Error> = database.run().await;
+/// Server settings that override config.yaml. Each flag falls back to its
+/// `SERVEUR_*` environment variable before the config file is used.
+#[derive(Args, Debug)]
+pub struct StartArgs {
+    /// Address to listen on
+    #[arg(long, env = "SERVEUR_HOST")]
+    host: Option<String>,
+    /// Port to listen on
+    #[arg(long, env = "SERVEUR_PORT")]
+    port: Option<u16>,
+    /// Number of worker threads, 0 for one per CPU core
+    #[arg(long, env = "SERVEUR_WORKERS")]
+    workers: Option<usize>,
+    /// Seconds an idle connection is kept open, 0 to disable keep-alive
+    #[arg(long, env = "SERVEUR_KEEP_ALIVE")]
+    keep_alive: Option<u64>,
+    /// Largest accepted request body in bytes
+    #[arg(long, env = "SERVEUR_MAX_BODY_SIZE")]
+    max_body_size: Option<usize>,
+    /// Reject every insert, update, delete and drop with 403
+    #[arg(long, env = "SERVEUR_READ_ONLY")]
+    read_only: bool,
+}
+
+impl StartArgs {
+    fn apply(&self, config: &mut ConfigLayout) {
+        if let Some(host) = &self.host {
+            config.host = Some(host.clone());
         }
-        _ => {
-            println!("Database not supported.");
+        config.port = self.port.or(config.port);
+        config.workers = self.workers.or(config.workers);
+        config.keep_alive = self.keep_alive.or(config.keep_alive);
+        config.max_body_size = self.max_body_size.or(config.max_body_size);
+        if self.read_only {
+            config.read_only = Some(true);
         }
     }
-    Ok(())
+}
+
+pub async fn start(args: &StartArgs, config_path: &Path) -> io::Result<()> {
+    let mut config: ConfigLayout = config::load(config_path)?;
+    args.apply(&mut config);
+    logging::init(config.log.as_ref())?;
+    let databases: Vec<(ConnectionConfig, Database)> = config
+        .connections()
+        .into_iter()
+        .map(|connection| {
+            let database: Database = Database::from_connection(&connection);
+            (connection, database)
+        })
+        .collect();
+    run(&config, databases).await
 }
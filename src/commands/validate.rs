@@ -0,0 +1,78 @@
+use super::{
+    config,
+    init::{ConfigLayout, ConnectionConfig, Database},
+};
+use crate::tls;
+use std::{path::Path, process::ExitCode, time::Duration};
+use tokio::time::timeout;
+
+/// Exit code when config.yaml cannot be read, parsed or validated.
+const CONFIG_FAILED: u8 = 1;
+/// Exit code when the config is valid but a database or certificate failed.
+const CHECK_FAILED: u8 = 2;
+const PING_TIMEOUT: Duration = Duration::from_secs(15);
+
+fn report(passed: bool, subject: &str, details: &str) {
+    let status: &str = match passed {
+        true => "pass",
+        false => "FAIL",
+    };
+    println!("[{status}] {subject}: {details}");
+}
+
+/// Checks the config file and every database it names without starting the
+/// server, printing one line per check. Exits with 0 when everything passed,
+/// 1 when the config itself is invalid and 2 when a connection failed.
+pub async fn validate(config_path: &Path) -> ExitCode {
+    let config: ConfigLayout = match config::load(config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            report(false, "config", &e.to_string());
+            return ExitCode::from(CONFIG_FAILED);
+        }
+    };
+    report(
+        true,
+        "config",
+        &format!("{} is valid", config_path.display()),
+    );
+
+    let mut passed: bool = true;
+    if let Some(settings) = &config.tls {
+        match tls::server_config(settings) {
+            Ok(_) => report(true, "tls", "certificate and key loaded"),
+            Err(e) => {
+                passed = false;
+                report(false, "tls", &e.to_string());
+            }
+        }
+    }
+    for connection in config.connections() {
+        let database: Database = Database::from_connection(&connection);
+        let subject: String = match database.prefix(&connection) {
+            Ok(prefix) => format!("{} at {prefix}", connection.database),
+            Err(_) => connection.database.to_string(),
+        };
+        match ping(&database, &connection).await {
+            Ok(()) => report(true, &subject, "connected"),
+            Err(e) => {
+                passed = false;
+                report(false, &subject, &e);
+            }
+        }
+    }
+    match passed {
+        true => ExitCode::SUCCESS,
+        false => ExitCode::from(CHECK_FAILED),
+    }
+}
+
+async fn ping(database: &Database, connection: &ConnectionConfig) -> Result<(), String> {
+    match timeout(PING_TIMEOUT, database.ping(connection)).await {
+        Ok(result) => result.map_err(|e| e.to_string()),
+        Err(_) => Err(format!(
+            "no answer after {} seconds",
+            PING_TIMEOUT.as_secs()
+        )),
+    }
+}
@@ -0,0 +1,231 @@
+use actix_web::{error::JsonPayloadError, http::StatusCode, HttpResponse, ResponseError};
+use deadpool_postgres::PoolError;
+use mongodb::error::{ErrorKind, WriteFailure};
+use serde_json::{json, Value};
+use std::fmt;
+use tokio_postgres::error::SqlState;
+use tracing::{error, warn};
+
+/// Error returned by every handler. It is rendered as
+/// `{"error": <reason>, "code": <status>, "details": <message>}`.
+#[derive(Debug)]
+pub enum ApiError {
+    BadRequest(String),
+    Unauthorized(String),
+    Forbidden(String),
+    NotFound(String),
+    Conflict(String),
+    PayloadTooLarge(String),
+    Internal(String),
+    ServiceUnavailable(String),
+}
+
+impl ApiError {
+    pub fn missing_field(tag: &str, expected: &str) -> ApiError {
+        ApiError::BadRequest(format!(
+            "The body should have a `{tag}` tag with {expected}"
+        ))
+    }
+
+    /// The JSON body of the error, also written as the last line of a
+    /// stream that fails after its headers were sent.
+    pub fn body(&self) -> Value {
+        let status: StatusCode = self.status_code();
+        json!({
+            "error": status.canonical_reason().unwrap_or("Error"),
+            "code": status.as_u16(),
+            "details": self.details(),
+        })
+    }
+
+    fn details(&self) -> &str {
+        match self {
+            ApiError::BadRequest(details)
+            | ApiError::Unauthorized(details)
+            | ApiError::Forbidden(details)
+            | ApiError::NotFound(details)
+            | ApiError::Conflict(details)
+            | ApiError::PayloadTooLarge(details)
+            | ApiError::Internal(details)
+            | ApiError::ServiceUnavailable(details) => details,
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.details())
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::ServiceUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(self.body())
+    }
+}
+
+impl From<JsonPayloadError> for ApiError {
+    fn from(e: JsonPayloadError) -> Self {
+        match e {
+            JsonPayloadError::Overflow { .. } | JsonPayloadError::OverflowKnownLength { .. } => {
+                ApiError::PayloadTooLarge(e.to_string())
+            }
+            _ => ApiError::BadRequest(e.to_string()),
+        }
+    }
+}
+
+impl From<bson::ser::Error> for ApiError {
+    fn from(e: bson::ser::Error) -> Self {
+        ApiError::BadRequest(format!(
+            "Unable to convert the body to a BSON document: {e}"
+        ))
+    }
+}
+
+/// A failure the client cannot fix. The driver's message names hosts, the
+/// replica set or the statement, so it is only logged.
+fn internal(database: &str, details: &str) -> ApiError {
+    error!(database, error = details, "database call failed");
+    ApiError::Internal(format!("The {database} call failed"))
+}
+
+/// The database could not be reached, or no connection freed up in time.
+fn unavailable(database: &str, details: &str) -> ApiError {
+    warn!(database, error = details, "database unavailable");
+    ApiError::ServiceUnavailable(format!("{database} is unavailable"))
+}
+
+// https://www.mongodb.com/docs/manual/reference/error-codes/
+const MONGO_DUPLICATE_KEY: i32 = 11000;
+const MONGO_NAMESPACE_NOT_FOUND: i32 = 26;
+
+impl From<mongodb::error::Error> for ApiError {
+    fn from(e: mongodb::error::Error) -> Self {
+        let details: String = e.to_string();
+        match e.kind.as_ref() {
+            ErrorKind::InvalidArgument { .. } | ErrorKind::BsonSerialization(_) => {
+                ApiError::BadRequest(details)
+            }
+            ErrorKind::Write(WriteFailure::WriteError(error)) => {
+                if error.code == MONGO_DUPLICATE_KEY {
+                    ApiError::Conflict(details)
+                } else {
+                    ApiError::BadRequest(details)
+                }
+            }
+            ErrorKind::BulkWrite(failure) => {
+                let duplicate: bool = failure
+                    .write_errors
+                    .iter()
+                    .flatten()
+                    .any(|error| error.code == MONGO_DUPLICATE_KEY);
+                if duplicate {
+                    ApiError::Conflict(details)
+                } else {
+                    ApiError::BadRequest(details)
+                }
+            }
+            ErrorKind::Command(error) if error.code == MONGO_NAMESPACE_NOT_FOUND => {
+                ApiError::NotFound(details)
+            }
+            ErrorKind::ServerSelection { .. } | ErrorKind::ConnectionPoolCleared { .. } => {
+                unavailable("MongoDB", &details)
+            }
+            _ => internal("MongoDB", &details),
+        }
+    }
+}
+
+/// The server's own message when there is one, as `Display` only says "db error".
+pub fn postgres_message(e: &tokio_postgres::Error) -> String {
+    match e.as_db_error() {
+        Some(db_error) => db_error.message().to_string(),
+        None => e.to_string(),
+    }
+}
+
+impl From<tokio_postgres::Error> for ApiError {
+    fn from(e: tokio_postgres::Error) -> Self {
+        let details: String = postgres_message(&e);
+        match e.code() {
+            Some(state)
+                if *state == SqlState::UNIQUE_VIOLATION
+                    || *state == SqlState::FOREIGN_KEY_VIOLATION
+                    || *state == SqlState::EXCLUSION_VIOLATION =>
+            {
+                ApiError::Conflict(details)
+            }
+            Some(state) if *state == SqlState::UNDEFINED_TABLE => ApiError::NotFound(details),
+            Some(state)
+                if *state == SqlState::UNDEFINED_COLUMN
+                    || *state == SqlState::NOT_NULL_VIOLATION
+                    || *state == SqlState::CHECK_VIOLATION
+                    // Class 22 covers malformed values such as `abc` for an integer.
+                    || state.code().starts_with("22") =>
+            {
+                ApiError::BadRequest(details)
+            }
+            _ if e.is_closed() => unavailable("Postgres", &details),
+            _ => internal("Postgres", &details),
+        }
+    }
+}
+
+impl From<PoolError> for ApiError {
+    fn from(e: PoolError) -> Self {
+        match e {
+            PoolError::Timeout(_) => unavailable("Postgres", "timed out waiting for a connection"),
+            PoolError::Backend(e) => unavailable("Postgres", &postgres_message(&e)),
+            e => internal("Postgres", &e.to_string()),
+        }
+    }
+}
+
+impl From<mysql_async::Error> for ApiError {
+    fn from(e: mysql_async::Error) -> Self {
+        let details: String = e.to_string();
+        match &e {
+            mysql_async::Error::Server(error) => match error.code {
+                // ER_DUP_ENTRY, ER_ROW_IS_REFERENCED_2, ER_NO_REFERENCED_ROW_2
+                1062 | 1451 | 1452 => ApiError::Conflict(details),
+                // ER_NO_SUCH_TABLE
+                1146 => ApiError::NotFound(details),
+                // ER_BAD_NULL_ERROR, ER_BAD_FIELD_ERROR, ER_TRUNCATED_WRONG_VALUE_FOR_FIELD,
+                // ER_WARN_DATA_OUT_OF_RANGE, ER_DATA_TOO_LONG
+                1048 | 1054 | 1366 | 1264 | 1406 => ApiError::BadRequest(details),
+                _ => internal("MySQL", &details),
+            },
+            mysql_async::Error::Io(_) => unavailable("MySQL", &details),
+            _ => internal("MySQL", &details),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use deadpool_postgres::TimeoutType;
+
+    #[test]
+    fn driver_details_stay_in_the_logs() {
+        let error: ApiError = ApiError::from(PoolError::Timeout(TimeoutType::Wait));
+        assert_eq!(error.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(error.to_string(), "Postgres is unavailable");
+        let error: ApiError = ApiError::from(mysql_async::Error::Other("SELECT secret".into()));
+        assert_eq!(error.body()["details"], "The MySQL call failed");
+    }
+}
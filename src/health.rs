@@ -0,0 +1,89 @@
+use crate::commands::init::Mount;
+use actix_web::{web, HttpResponse, Responder};
+use futures::future::join_all;
+use serde_json::{json, Value};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::time::timeout;
+use tracing::warn;
+
+/// How long `/readyz` waits for each database before reporting it down.
+const READY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What the probes need to know about the running server.
+pub struct Health {
+    started: Instant,
+    mounts: Arc<Vec<Mount>>,
+}
+
+impl Health {
+    pub fn new(mounts: Arc<Vec<Mount>>) -> Health {
+        Health {
+            started: Instant::now(),
+            mounts,
+        }
+    }
+}
+
+/// The process is up and answering requests.
+async fn healthz() -> impl Responder {
+    HttpResponse::Ok().json(json!({ "status": "ok" }))
+}
+
+/// Every database answers a ping. Responds 503 with the failing ones
+/// otherwise, so the orchestrator stops sending traffic. The route needs no
+/// key, so why a database is down is only logged.
+async fn readyz(health: web::Data<Health>) -> impl Responder {
+    let checks = health.mounts.iter().map(|mount| async move {
+        let error: Option<String> = match timeout(READY_TIMEOUT, mount.ping()).await {
+            Ok(Ok(())) => None,
+            Ok(Err(e)) => Some(e.to_string()),
+            Err(_) => Some(format!(
+                "No answer after {} seconds",
+                READY_TIMEOUT.as_secs()
+            )),
+        };
+        if let Some(error) = &error {
+            warn!(prefix = mount.prefix(), error, "database not ready");
+        }
+        json!({
+            "database": mount.database().to_string(),
+            "prefix": mount.prefix(),
+            "ready": error.is_none(),
+        })
+    });
+    let databases: Vec<Value> = join_all(checks).await;
+    let ready: bool = databases.iter().all(|check| check["ready"] == true);
+    let body: Value = json!({
+        "status": if ready { "ready" } else { "unavailable" },
+        "databases": databases,
+    });
+    match ready {
+        true => HttpResponse::Ok().json(body),
+        false => HttpResponse::ServiceUnavailable().json(body),
+    }
+}
+
+async fn version(health: web::Data<Health>) -> impl Responder {
+    let databases: Vec<Value> = health
+        .mounts
+        .iter()
+        .map(|mount| json!({ "database": mount.database().to_string(), "prefix": mount.prefix() }))
+        .collect();
+    HttpResponse::Ok().json(json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "databases": databases,
+        "uptime_seconds": health.started.elapsed().as_secs(),
+    }))
+}
+
+/// Probe routes. They are registered outside the database scopes so they
+/// do not need the api key.
+pub fn health_config(cfg: &mut web::ServiceConfig, health: web::Data<Health>) {
+    cfg.app_data(health)
+        .route("/healthz", web::get().to(healthz))
+        .route("/readyz", web::get().to(readyz))
+        .route("/version", web::get().to(version));
+}
@@ -0,0 +1,100 @@
+use crate::{
+    commands::init::{LogConfig, LogFormat},
+    metrics,
+};
+use mongodb::event::command::{CommandEventHandler, CommandFailedEvent, CommandSucceededEvent};
+use std::{
+    future::Future,
+    io::{self, IsTerminal},
+    time::{Duration, Instant},
+};
+use tracing::{debug, warn};
+use tracing_subscriber::EnvFilter;
+
+const DEFAULT_LEVEL: &str = "info";
+
+pub fn filter(level: &str) -> io::Result<EnvFilter> {
+    EnvFilter::try_new(level).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid log level `{level}`: {e}"),
+        )
+    })
+}
+
+/// Installs the global subscriber. Logs go to stdout, one line per event,
+/// with the fields of the request span they happened in.
+pub fn init(config: Option<&LogConfig>) -> io::Result<()> {
+    let config: LogConfig = config.cloned().unwrap_or_default();
+    let filter: EnvFilter = match EnvFilter::try_from_default_env() {
+        Ok(filter) => filter,
+        Err(_) => filter(config.level.as_deref().unwrap_or(DEFAULT_LEVEL))?,
+    };
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_ansi(io::stdout().is_terminal());
+    let installed = match config.format.unwrap_or_default() {
+        LogFormat::Pretty => builder.try_init(),
+        LogFormat::Json => builder
+            .json()
+            .flatten_event(true)
+            .with_span_list(false)
+            .try_init(),
+    };
+    installed.map_err(|e| io::Error::other(e.to_string()))
+}
+
+/// Logs how long a Postgres statement took, inside the current request
+/// span, and adds it to the query duration histogram.
+pub async fn timed<T, E, F>(statement: &str, query: F) -> Result<T, E>
+where
+    F: Future<Output = Result<T, E>>,
+{
+    let started: Instant = Instant::now();
+    let result: Result<T, E> = query.await;
+    let elapsed: Duration = started.elapsed();
+    let operation: String = statement
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_uppercase();
+    metrics::observe_query("postgres", &operation, result.is_ok(), elapsed);
+    debug!(
+        db.system = "postgres",
+        db.statement = statement,
+        elapsed_ms = elapsed.as_secs_f64() * 1000.0,
+        ok = result.is_ok(),
+        "statement finished"
+    );
+    result
+}
+
+/// The Mongo driver reports each command it sends with its round trip
+/// time. Handlers are awaited inside the request span, so these events
+/// carry the request id.
+pub struct MongoCommandLog;
+
+impl CommandEventHandler for MongoCommandLog {
+    fn handle_command_succeeded_event(&self, event: CommandSucceededEvent) {
+        metrics::observe_query("mongodb", &event.command_name, true, event.duration);
+        debug!(
+            db.system = "mongodb",
+            db.operation = event.command_name,
+            elapsed_ms = event.duration.as_secs_f64() * 1000.0,
+            ok = true,
+            "command finished"
+        );
+    }
+
+    fn handle_command_failed_event(&self, event: CommandFailedEvent) {
+        metrics::observe_query("mongodb", &event.command_name, false, event.duration);
+        warn!(
+            db.system = "mongodb",
+            db.operation = event.command_name,
+            elapsed_ms = event.duration.as_secs_f64() * 1000.0,
+            ok = false,
+            error = %event.failure,
+            "command failed"
+        );
+    }
+}
@@ -1,18 +1,36 @@
 use clap::{Parser, Subcommand};
-use commands::init::init;
-use commands::start::start;
-use std::io;
+use commands::init::{init, InitArgs};
+use commands::start::{start, StartArgs};
+use commands::validate::validate;
+use std::{path::PathBuf, process::ExitCode};
+mod body;
 mod commands;
+mod config_text;
+mod error;
+mod health;
+mod logging;
+mod metrics;
+mod middleware;
 mod mongo;
+mod mysql;
 mod postgresql;
-mod config_text;
-
+mod stream;
+#[cfg(test)]
+mod test_support;
+mod tls;
 
 #[derive(Parser)]
 #[command(author, version, about , long_about=None)]
 #[command(propagate_version = true)]
-
-struct CLI {
+struct Cli {
+    /// Path of the configuration file
+    #[arg(
+        long,
+        global = true,
+        env = "SERVEUR_CONFIG",
+        default_value = "config.yaml"
+    )]
+    config: PathBuf,
     #[command(subcommand)]
     command: Arguments,
 }
@@ -20,21 +38,30 @@ struct CLI {
 #[derive(Subcommand)]
 enum Arguments {
     /// Creates a configuration file for the serveur
-    Init,
+    Init(InitArgs),
     /// Starts the server with the given configuration file
-    Start,
+    Start(StartArgs),
+    /// Checks the configuration file and connects to every database in it
+    Validate,
 }
 
 #[tokio::main]
-async fn main() -> io::Result<()> {
-    let cli: CLI = CLI::parse();
-    let _ = match &cli.command {
-        Arguments::Init => {
-            let _ = init();
+async fn main() -> ExitCode {
+    let cli: Cli = Cli::parse();
+    match &cli.command {
+        Arguments::Init(args) => {
+            if let Err(e) = init(args, &cli.config) {
+                eprintln!("Unable to create {}: {e}", cli.config.display());
+                return ExitCode::FAILURE;
+            }
         }
-        Arguments::Start => {
-            let _ = start().await;
+        Arguments::Start(args) => {
+            if let Err(e) = start(args, &cli.config).await {
+                eprintln!("Unable to start the serveur: {e}");
+                return ExitCode::FAILURE;
+            }
         }
+        Arguments::Validate => return validate(&cli.config).await,
     };
-    Ok(())
+    ExitCode::SUCCESS
 }
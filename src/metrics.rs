@@ -0,0 +1,158 @@
+use crate::commands::init::Mount;
+use actix_web::{web, HttpResponse};
+use prometheus::{
+    histogram_opts, opts, Encoder, HistogramVec, IntCounterVec, IntGaugeVec, Registry, TextEncoder,
+};
+use std::{sync::LazyLock, time::Duration};
+
+/// Label used for requests that matched no route, so that scanners probing
+/// random paths do not create a series per path.
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// Buckets in seconds, from a cached lookup to a slow aggregation.
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 10.0,
+];
+
+struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    errors: IntCounterVec,
+    latency: HistogramVec,
+    queries: HistogramVec,
+    pool_connections: IntGaugeVec,
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(|| {
+    let registry: Registry = Registry::new();
+    let requests: IntCounterVec = IntCounterVec::new(
+        opts!(
+            "http_requests_total",
+            "Requests answered, by route and status"
+        ),
+        &["method", "route", "status"],
+    )
+    .expect("valid metric");
+    let errors: IntCounterVec = IntCounterVec::new(
+        opts!(
+            "http_request_errors_total",
+            "Requests answered with a 4xx or 5xx status"
+        ),
+        &["route", "status"],
+    )
+    .expect("valid metric");
+    let latency: HistogramVec = HistogramVec::new(
+        histogram_opts!(
+            "http_request_duration_seconds",
+            "Time from receiving a request to sending its response headers",
+            LATENCY_BUCKETS.to_vec()
+        ),
+        &["method", "route"],
+    )
+    .expect("valid metric");
+    let queries: HistogramVec = HistogramVec::new(
+        histogram_opts!(
+            "db_query_duration_seconds",
+            "Time spent in the database driver, by statement or command",
+            LATENCY_BUCKETS.to_vec()
+        ),
+        &["system", "operation", "outcome"],
+    )
+    .expect("valid metric");
+    let pool_connections: IntGaugeVec = IntGaugeVec::new(
+        opts!(
+            "db_pool_connections",
+            "Postgres pool connections by state: in_use, idle, max and waiting"
+        ),
+        &["prefix", "state"],
+    )
+    .expect("valid metric");
+    for collector in [
+        Box::new(requests.clone()) as Box<dyn prometheus::core::Collector>,
+        Box::new(errors.clone()),
+        Box::new(latency.clone()),
+        Box::new(queries.clone()),
+        Box::new(pool_connections.clone()),
+    ] {
+        registry
+            .register(collector)
+            .expect("metric registered once");
+    }
+    Metrics {
+        registry,
+        requests,
+        errors,
+        latency,
+        queries,
+        pool_connections,
+    }
+});
+
+pub fn observe_request(method: &str, route: Option<&str>, status: u16, elapsed: Duration) {
+    let route: &str = route.unwrap_or(UNMATCHED_ROUTE);
+    let status: String = status.to_string();
+    METRICS
+        .requests
+        .with_label_values(&[method, route, &status])
+        .inc();
+    if status.starts_with('4') || status.starts_with('5') {
+        METRICS.errors.with_label_values(&[route, &status]).inc();
+    }
+    METRICS
+        .latency
+        .with_label_values(&[method, route])
+        .observe(elapsed.as_secs_f64());
+}
+
+/// `operation` is the SQL keyword or the Mongo command name, never the
+/// full statement, to keep the number of series bounded.
+pub fn observe_query(system: &str, operation: &str, ok: bool, elapsed: Duration) {
+    let outcome: &str = if ok { "ok" } else { "error" };
+    METRICS
+        .queries
+        .with_label_values(&[system, operation, outcome])
+        .observe(elapsed.as_secs_f64());
+}
+
+/// Pool gauges are read when scraped rather than kept up to date on every
+/// checkout.
+fn record_pools(mounts: &[Mount]) {
+    for mount in mounts {
+        let Mount::Postgres(prefix, state) = mount else {
+            continue;
+        };
+        let status = state.db.status();
+        let in_use: usize = status.size.saturating_sub(status.available);
+        let states: [(&str, usize); 4] = [
+            ("in_use", in_use),
+            ("idle", status.available),
+            ("max", status.max_size),
+            ("waiting", status.waiting),
+        ];
+        for (name, value) in states {
+            METRICS
+                .pool_connections
+                .with_label_values(&[prefix.as_str(), name])
+                .set(value as i64);
+        }
+    }
+}
+
+async fn metrics(mounts: web::Data<Vec<Mount>>) -> HttpResponse {
+    record_pools(&mounts);
+    let encoder: TextEncoder = TextEncoder::new();
+    let mut body: Vec<u8> = Vec::new();
+    match encoder.encode(&METRICS.registry.gather(), &mut body) {
+        Ok(()) => HttpResponse::Ok()
+            .content_type(encoder.format_type())
+            .body(body),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// `/metrics` in the Prometheus text format. Like the health probes it is
+/// served without the api key.
+pub fn metrics_config(cfg: &mut web::ServiceConfig, mounts: web::Data<Vec<Mount>>) {
+    cfg.app_data(mounts)
+        .route("/metrics", web::get().to(metrics));
+}
@@ -0,0 +1,312 @@
+use crate::commands::init::RuleConfig;
+use actix_web::dev::{Path, ResourceDef, ServiceRequest};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use percent_encoding::percent_decode_str;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operation {
+    Read,
+    Write,
+    Drop,
+}
+
+/// Every route name of the Mongo, Postgres and MySQL scopes, i.e. the first
+/// path segment after the prefix, with what the route does. A route missing
+/// here counts as a write, so it is never readable by accident. Connection
+/// names may not take one, since `<scope>/<name>` would shadow the route.
+pub const ACTIONS: [(&str, Operation); 23] = [
+    // The index page of each scope.
+    ("", Operation::Read),
+    ("find_one", Operation::Read),
+    ("find_many", Operation::Read),
+    ("query", Operation::Read),
+    ("get_all_databases", Operation::Read),
+    ("get_collections", Operation::Read),
+    ("get_schemas", Operation::Read),
+    ("get_tables", Operation::Read),
+    ("describe", Operation::Read),
+    ("insert_one", Operation::Write),
+    ("insert_many", Operation::Write),
+    ("update_one", Operation::Write),
+    ("update_many", Operation::Write),
+    ("delete_one", Operation::Write),
+    ("delete_many", Operation::Write),
+    ("rename_field", Operation::Write),
+    ("pop_first", Operation::Write),
+    ("pop_last", Operation::Write),
+    ("push_element", Operation::Write),
+    ("increment", Operation::Write),
+    ("drop_database", Operation::Drop),
+    ("drop_collection", Operation::Drop),
+    ("drop_table", Operation::Drop),
+];
+
+impl Operation {
+    pub fn of(action: &str) -> Operation {
+        ACTIONS
+            .iter()
+            .find(|(name, _)| *name == action)
+            .map(|(_, operation)| *operation)
+            .unwrap_or(Operation::Write)
+    }
+
+    fn parse(name: &str) -> Option<Operation> {
+        match name {
+            "read" => Some(Operation::Read),
+            "write" => Some(Operation::Write),
+            "drop" => Some(Operation::Drop),
+            _ => None,
+        }
+    }
+}
+
+/// What a request is about to do, worked out before it reaches a handler.
+#[derive(Debug)]
+pub struct Target {
+    /// The route name, i.e. the first path segment after the scope prefix.
+    pub action: String,
+    pub operation: Operation,
+    /// The `{database}` and `{collection}` parameters of Mongo routes.
+    pub database: Option<String>,
+    pub collection: Option<String>,
+    /// `database.collection`, `database.*` for a whole Mongo database, the
+    /// table for SQL routes, or None for routes that list databases.
+    pub resource: Option<String>,
+}
+
+impl Target {
+    /// Called from a scope middleware, where the scope prefix has already
+    /// been consumed but the route parameters are not parsed yet, so they
+    /// are captured from the pattern the path will be routed to. They are
+    /// percent-decoded like `web::Path` does, so that the checks see the
+    /// same names as the handlers.
+    pub fn of(req: &ServiceRequest) -> Target {
+        let action: String = req
+            .match_info()
+            .unprocessed()
+            .trim_start_matches('/')
+            .split('/')
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        let mut params: Path<String> = Path::new(req.path().to_string());
+        if let Some(pattern) = req.request().match_pattern() {
+            ResourceDef::new(pattern).capture_match_info(&mut params);
+        }
+        let param = |name: &str| -> Option<String> {
+            params
+                .get(name)
+                .map(|value| percent_decode_str(value).decode_utf8_lossy().into_owned())
+        };
+        let database: Option<String> = param("database");
+        let collection: Option<String> = param("collection");
+        let resource: Option<String> =
+            match (&database, &collection, param("table"), param("schema")) {
+                (Some(database), Some(collection), _, _) => {
+                    Some(format!("{database}.{collection}"))
+                }
+                (Some(database), None, _, _) => Some(format!("{database}.*")),
+                (None, _, Some(table), _) => Some(table),
+                (None, _, None, Some(schema)) => Some(format!("{schema}.*")),
+                (None, _, None, None) => None,
+            };
+        Target {
+            operation: Operation::of(&action),
+            action,
+            database,
+            collection,
+            resource,
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.resource {
+            Some(resource) => write!(f, "`{}` on `{resource}`", self.action),
+            None => write!(f, "`{}`", self.action),
+        }
+    }
+}
+
+pub fn glob_set<S: AsRef<str>>(patterns: &[S]) -> Result<GlobSet, String> {
+    let mut builder: GlobSetBuilder = GlobSetBuilder::new();
+    for pattern in patterns.iter().map(AsRef::as_ref) {
+        builder.add(Glob::new(pattern).map_err(|e| format!("Invalid pattern `{pattern}`: {e}"))?);
+    }
+    builder.build().map_err(|e| e.to_string())
+}
+
+#[derive(Debug)]
+struct Rule {
+    operations: Vec<Operation>,
+    actions: GlobSet,
+    resources: GlobSet,
+}
+
+impl Rule {
+    fn from_config(config: &RuleConfig) -> Result<Rule, String> {
+        let operations: Vec<Operation> = config
+            .operations
+            .iter()
+            .filter_map(|name| Operation::parse(name))
+            .collect();
+        let actions: Vec<&str> = config
+            .operations
+            .iter()
+            .map(String::as_str)
+            .filter(|name| Operation::parse(name).is_none())
+            .collect();
+        let resources: Vec<&str> = config.resources.iter().map(String::as_str).collect();
+        Ok(Rule {
+            operations,
+            actions: glob_set(&actions)?,
+            resources: glob_set(&resources)?,
+        })
+    }
+
+    /// Routes without a resource list every database, schema or table, so a
+    /// rule limited to some resources must name them explicitly. The index
+    /// route shows nothing and only needs the operation.
+    fn allows(&self, target: &Target) -> bool {
+        let operation: bool =
+            self.operations.contains(&target.operation) || self.actions.is_match(&target.action);
+        match &target.resource {
+            Some(resource) => operation && self.resources.is_match(resource),
+            None if target.action.is_empty() => operation,
+            None => self.actions.is_match(&target.action),
+        }
+    }
+}
+
+/// A named list of rules. A request is allowed when any rule allows it.
+#[derive(Debug)]
+pub struct Role {
+    name: String,
+    rules: Vec<Rule>,
+}
+
+impl Role {
+    pub fn from_config(name: &str, rules: &[RuleConfig]) -> Result<Role, String> {
+        Ok(Role {
+            name: name.to_string(),
+            rules: rules
+                .iter()
+                .map(Rule::from_config)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn allows(&self, target: &Target) -> bool {
+        self.rules.iter().any(|rule| rule.allows(target))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::assert_statuses;
+    use actix_web::http::StatusCode;
+
+    fn rule(operations: &[&str], resources: &[&str]) -> RuleConfig {
+        RuleConfig {
+            operations: operations.iter().map(|name| name.to_string()).collect(),
+            resources: resources.iter().map(|name| name.to_string()).collect(),
+        }
+    }
+
+    fn target(action: &str, resource: Option<&str>) -> Target {
+        Target {
+            action: action.to_string(),
+            operation: Operation::of(action),
+            database: None,
+            collection: None,
+            resource: resource.map(str::to_string),
+        }
+    }
+
+    fn analyst() -> Role {
+        Role::from_config(
+            "analyst",
+            &[
+                rule(&["read"], &["analytics.*"]),
+                rule(&["insert_*"], &["app.events"]),
+            ],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn operations_of_routes() {
+        assert_eq!(Operation::of("find_many"), Operation::Read);
+        assert_eq!(Operation::of("get_tables"), Operation::Read);
+        assert_eq!(Operation::of("update_one"), Operation::Write);
+        assert_eq!(Operation::of("drop_table"), Operation::Drop);
+        assert_eq!(Operation::of("a_new_route"), Operation::Write);
+    }
+
+    #[test]
+    fn operations_apply_to_matching_resources() {
+        let role: Role = analyst();
+        assert!(role.allows(&target("find_one", Some("analytics.daily"))));
+        assert!(role.allows(&target("find_many", Some("analytics.*"))));
+        assert!(!role.allows(&target("find_one", Some("app.users"))));
+        assert!(!role.allows(&target("delete_one", Some("analytics.daily"))));
+        assert!(!role.allows(&target("drop_collection", Some("analytics.daily"))));
+    }
+
+    #[test]
+    fn route_globs_grant_single_routes() {
+        let role: Role = analyst();
+        assert!(role.allows(&target("insert_one", Some("app.events"))));
+        assert!(role.allows(&target("insert_many", Some("app.events"))));
+        assert!(!role.allows(&target("update_one", Some("app.events"))));
+        assert!(!role.allows(&target("insert_one", Some("app.users"))));
+    }
+
+    #[test]
+    fn listings_need_an_explicit_grant() {
+        let role: Role = analyst();
+        assert!(role.allows(&target("", None)));
+        assert!(!role.allows(&target("get_all_databases", None)));
+        assert!(!role.allows(&target("get_tables", None)));
+        let role: Role =
+            Role::from_config("browser", &[rule(&["get_all_databases"], &[])]).unwrap();
+        assert!(role.allows(&target("get_all_databases", None)));
+        assert!(!role.allows(&target("get_schemas", None)));
+    }
+
+    #[actix_web::test]
+    async fn roles_see_percent_decoded_names() {
+        let config: &str = concat!(
+            "api_key: \"\"\n",
+            "api_keys:\n",
+            "  - key: r\n",
+            "    role: reader\n",
+            "roles:\n",
+            "  reader:\n",
+            "    - operations: [read]\n",
+            "      resources: [\"app.my coll\"]\n",
+        );
+        assert_statuses(
+            config,
+            "r",
+            &[
+                ("/mongodb/find_one/app/my%20coll", StatusCode::OK),
+                ("/mongodb/find_one/%61pp/my%20coll", StatusCode::OK),
+                ("/mongodb/find_one/app/other", StatusCode::FORBIDDEN),
+            ],
+        )
+        .await;
+    }
+
+    #[test]
+    fn invalid_globs_are_rejected() {
+        assert!(Role::from_config("broken", &[rule(&["read"], &["app.[users"])]).is_err());
+    }
+}
@@ -0,0 +1,188 @@
+use super::access::{Operation, Role, Target};
+use crate::{commands::init::ConfigLayout, error::ApiError, mongo::expose::Expose};
+use actix_web::{
+    body::EitherBody,
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::HeaderName,
+    web::Data,
+    Error, HttpResponse, ResponseError,
+};
+use futures::future::{ready, LocalBoxFuture, Ready};
+use std::{collections::HashMap, io, sync::Arc};
+
+const DEFAULT_AUTH_HEADER: &str = "x-api-key";
+
+/// The keys accepted by the server. The top-level `api_key` has full
+/// access and each entry of `api_keys` is limited to its role. With
+/// `read_only` set, every key is limited to reads.
+#[derive(Debug, Clone)]
+pub struct ApiKey {
+    header: HeaderName,
+    key: String,
+    scoped: Vec<(String, Arc<Role>)>,
+    read_only: bool,
+}
+
+/// What the key of a request grants.
+enum Access<'a> {
+    Full,
+    Role(&'a Role),
+}
+
+impl ApiKey {
+    pub fn from_config(config: &ConfigLayout) -> io::Result<ApiKey> {
+        let header: &str = match config.auth_header.as_deref().map(str::trim) {
+            Some(header) if !header.is_empty() => header,
+            _ => DEFAULT_AUTH_HEADER,
+        };
+        let header: HeaderName = HeaderName::try_from(header).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid auth_header `{header}`: {e}"),
+            )
+        })?;
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+        let mut roles: HashMap<&str, Arc<Role>> = HashMap::new();
+        for (name, rules) in config.roles.iter().flatten() {
+            let role: Role = Role::from_config(name, rules)
+                .map_err(|e| invalid(format!("Invalid role `{name}`: {e}")))?;
+            roles.insert(name, Arc::new(role));
+        }
+        let mut scoped: Vec<(String, Arc<Role>)> = Vec::new();
+        for key in config.api_keys.iter().flatten() {
+            let role: &Arc<Role> = roles
+                .get(key.role.as_str())
+                .ok_or_else(|| invalid(format!("Unknown role `{}`", key.role)))?;
+            scoped.push((key.key.clone(), role.clone()));
+        }
+        if config.api_key.is_empty() && scoped.is_empty() {
+            return Err(invalid(String::from(
+                "api_key must be set in config.yaml before starting the serveur",
+            )));
+        }
+        Ok(ApiKey {
+            header,
+            key: config.api_key.clone(),
+            scoped,
+            read_only: config.read_only.unwrap_or(false),
+        })
+    }
+
+    pub fn header(&self) -> &HeaderName {
+        &self.header
+    }
+
+    fn access(&self, req: &ServiceRequest) -> Option<Access<'_>> {
+        let value: &str = req
+            .headers()
+            .get(&self.header)
+            .and_then(|value| value.to_str().ok())?;
+        let value: &str = if self.header == actix_web::http::header::AUTHORIZATION {
+            value.strip_prefix("Bearer ").unwrap_or(value)
+        } else {
+            value
+        };
+        let value: &[u8] = value.trim().as_bytes();
+        if !self.key.is_empty() && constant_time_eq(value, self.key.as_bytes()) {
+            return Some(Access::Full);
+        }
+        self.scoped
+            .iter()
+            .find(|(key, _)| !key.is_empty() && constant_time_eq(value, key.as_bytes()))
+            .map(|(_, role)| Access::Role(role))
+    }
+}
+
+// Compares the whole key regardless of where the first mismatch is, so the
+// response time does not leak how much of a guessed key was correct.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Rejects every request that does not carry one of the configured api
+/// keys. Once the key is known, names hidden by `expose` answer 404, and
+/// the writes of a read-only server and the requests a scoped key's role
+/// does not allow answer 403. The keys are read from the `Data<ApiKey>`
+/// registered on the `App`.
+pub struct RequireApiKey;
+
+impl<S, B> Transform<S, ServiceRequest> for RequireApiKey
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = RequireApiKeyMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequireApiKeyMiddleware { service }))
+    }
+}
+
+pub struct RequireApiKeyMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for RequireApiKeyMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let api_key: Option<Data<ApiKey>> = req.app_data::<Data<ApiKey>>().cloned();
+        let denied: Option<ApiError> = match api_key.as_ref().and_then(|key| key.access(&req)) {
+            Some(access) => {
+                let target: Target = Target::of(&req);
+                let read_only: bool = api_key.as_ref().is_some_and(|key| key.read_only);
+                let hidden: Option<ApiError> = req
+                    .app_data::<Data<Expose>>()
+                    .and_then(|expose| expose.check(&target).err());
+                match access {
+                    _ if hidden.is_some() => hidden,
+                    _ if read_only && target.operation != Operation::Read => {
+                        Some(ApiError::Forbidden(format!(
+                            "The serveur is read-only, {target} is not allowed"
+                        )))
+                    }
+                    Access::Role(role) if !role.allows(&target) => Some(ApiError::Forbidden(
+                        format!("The `{}` role may not run {target}", role.name()),
+                    )),
+                    _ => None,
+                }
+            }
+            None => {
+                let header: String = api_key
+                    .as_ref()
+                    .map(|api_key| api_key.header().to_string())
+                    .unwrap_or_else(|| DEFAULT_AUTH_HEADER.to_string());
+                Some(ApiError::Unauthorized(format!(
+                    "Missing or invalid `{header}` header"
+                )))
+            }
+        };
+        match denied {
+            None => {
+                let response = self.service.call(req);
+                Box::pin(async move { Ok(response.await?.map_into_left_body()) })
+            }
+            Some(e) => {
+                let response: HttpResponse = e.error_response();
+                Box::pin(async move { Ok(req.into_response(response).map_into_right_body()) })
+            }
+        }
+    }
+}
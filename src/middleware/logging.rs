@@ -0,0 +1,119 @@
+use crate::metrics;
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::{HeaderName, HeaderValue},
+    Error,
+};
+use futures::future::{ready, LocalBoxFuture, Ready};
+use std::time::{Duration, Instant};
+use tracing::{error, field, info, info_span, Instrument, Span};
+use uuid::Uuid;
+
+const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Runs every request inside a `request` span and logs one line when it is
+/// answered, which is also where the request metrics are recorded. The id
+/// comes from the `x-request-id` header when the client or a proxy sent
+/// one, and is echoed back in the response.
+pub struct RequestLog;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestLog
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RequestLogMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestLogMiddleware { service }))
+    }
+}
+
+pub struct RequestLogMiddleware<S> {
+    service: S,
+}
+
+/// Route parameters are only known once the request has been routed, so
+/// they are added to the span when the response comes back.
+fn record_route(span: &Span, response: &ServiceResponse<impl Sized>) {
+    let request = response.request();
+    if let Some(route) = request.match_pattern() {
+        span.record("route", route);
+    }
+    for name in ["database", "collection", "table"] {
+        if let Some(value) = request.match_info().get(name) {
+            span.record(name, value);
+        }
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for RequestLogMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let request_id: String = req
+            .headers()
+            .get(&REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| !value.is_empty())
+            .map(str::to_string)
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+        let span: Span = info_span!(
+            "request",
+            request_id = %request_id,
+            method = %req.method(),
+            path = req.path(),
+            route = field::Empty,
+            database = field::Empty,
+            collection = field::Empty,
+            table = field::Empty,
+        );
+        let started: Instant = Instant::now();
+        let response = span.in_scope(|| self.service.call(req));
+        Box::pin(
+            async move {
+                let mut response: ServiceResponse<B> = match response.await {
+                    Ok(response) => response,
+                    Err(e) => {
+                        let elapsed_ms: f64 = started.elapsed().as_secs_f64() * 1000.0;
+                        error!(error = %e, elapsed_ms, "request failed");
+                        return Err(e);
+                    }
+                };
+                record_route(&Span::current(), &response);
+                let status: u16 = response.status().as_u16();
+                let elapsed: Duration = started.elapsed();
+                let elapsed_ms: f64 = elapsed.as_secs_f64() * 1000.0;
+                metrics::observe_request(
+                    response.request().method().as_str(),
+                    response.request().match_pattern().as_deref(),
+                    status,
+                    elapsed,
+                );
+                match status {
+                    500.. => error!(status, elapsed_ms, "request finished"),
+                    _ => info!(status, elapsed_ms, "request finished"),
+                }
+                if let Ok(value) = HeaderValue::from_str(&request_id) {
+                    response.headers_mut().insert(REQUEST_ID_HEADER, value);
+                }
+                Ok(response)
+            }
+            .instrument(span),
+        )
+    }
+}
@@ -0,0 +1,3 @@
+pub mod access;
+pub mod auth;
+pub mod logging;
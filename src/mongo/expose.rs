@@ -0,0 +1,137 @@
+use crate::{
+    commands::init::{ConfigLayout, PatternsConfig},
+    error::ApiError,
+    middleware::access::{glob_set, Target},
+};
+use globset::GlobSet;
+
+struct Patterns {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl Patterns {
+    fn from_config(config: Option<&PatternsConfig>) -> Result<Patterns, String> {
+        let config: PatternsConfig = config.cloned().unwrap_or_default();
+        Ok(Patterns {
+            include: config.include.as_deref().map(glob_set).transpose()?,
+            exclude: glob_set(config.exclude.as_deref().unwrap_or_default())?,
+        })
+    }
+
+    /// True when any of the spellings of a name is included and none is
+    /// excluded.
+    fn allows(&self, names: &[&str]) -> bool {
+        let included: bool = match &self.include {
+            Some(include) => names.iter().any(|name| include.is_match(name)),
+            None => true,
+        };
+        included && !names.iter().any(|name| self.exclude.is_match(name))
+    }
+}
+
+/// The `expose` section of config.yaml. Collection patterns are matched
+/// against both `collection` and `database.collection`, so `system.*`
+/// hides a prefix everywhere and `app.sessions` a single collection.
+pub struct Expose {
+    databases: Patterns,
+    collections: Patterns,
+}
+
+impl Expose {
+    pub fn from_config(config: &ConfigLayout) -> Result<Expose, String> {
+        let expose = config.expose.as_ref();
+        Ok(Expose {
+            databases: Patterns::from_config(expose.and_then(|expose| expose.databases.as_ref()))
+                .map_err(|e| format!("expose.databases: {e}"))?,
+            collections: Patterns::from_config(
+                expose.and_then(|expose| expose.collections.as_ref()),
+            )
+            .map_err(|e| format!("expose.collections: {e}"))?,
+        })
+    }
+
+    pub fn database(&self, database: &str) -> bool {
+        self.databases.allows(&[database])
+    }
+
+    pub fn collection(&self, database: &str, collection: &str) -> bool {
+        let qualified: String = format!("{database}.{collection}");
+        self.database(database) && self.collections.allows(&[collection, &qualified])
+    }
+
+    /// Answers 404 for hidden names, as if they did not exist, so that a
+    /// client cannot tell them apart from missing ones.
+    pub fn check(&self, target: &Target) -> Result<(), ApiError> {
+        match (&target.database, &target.collection) {
+            (Some(database), _) if !self.database(database) => {
+                Err(ApiError::NotFound(format!("Database {database} not found")))
+            }
+            (Some(database), Some(collection)) if !self.collection(database, collection) => Err(
+                ApiError::NotFound(format!("Collection {collection} not found in {database}")),
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::assert_statuses;
+    use actix_web::http::StatusCode;
+
+    const CONFIG: &str = r#"
+api_key: "k"
+expose:
+  databases:
+    exclude: ["admin"]
+  collections:
+    exclude: ["system.*", "app.sessions"]
+"#;
+
+    fn expose() -> Expose {
+        let config: ConfigLayout = serde_yaml::from_str(CONFIG).unwrap();
+        Expose::from_config(&config).unwrap()
+    }
+
+    #[test]
+    fn hides_excluded_names() {
+        let expose: Expose = expose();
+        assert!(!expose.database("admin"));
+        assert!(expose.database("app"));
+        assert!(!expose.collection("app", "system.users"));
+        assert!(!expose.collection("app", "sessions"));
+        assert!(expose.collection("other", "sessions"));
+        assert!(!expose.collection("admin", "users"));
+    }
+
+    #[test]
+    fn include_limits_to_matches() {
+        let config: ConfigLayout =
+            serde_yaml::from_str("api_key: k\nexpose:\n  databases:\n    include: [\"app*\"]\n")
+                .unwrap();
+        let expose: Expose = Expose::from_config(&config).unwrap();
+        assert!(expose.database("app"));
+        assert!(expose.database("app_test"));
+        assert!(!expose.database("billing"));
+    }
+
+    #[actix_web::test]
+    async fn percent_encoded_names_are_checked_decoded() {
+        assert_statuses(
+            CONFIG,
+            "k",
+            &[
+                ("/mongodb/find_one/app/users", StatusCode::OK),
+                ("/mongodb/find_one/adm%69n/users", StatusCode::NOT_FOUND),
+                (
+                    "/mongodb/find_one/app/system%2Eusers",
+                    StatusCode::NOT_FOUND,
+                ),
+                ("/mongodb/find_one/app/%73essions", StatusCode::NOT_FOUND),
+            ],
+        )
+        .await;
+    }
+}
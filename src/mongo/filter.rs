@@ -0,0 +1,486 @@
+use crate::{commands::init::ConfigLayout, error::ApiError};
+use bson::{doc, oid::ObjectId, Bson, DateTime, Document};
+use chrono::NaiveDate;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// The type a query-string value is converted to, chosen with a `field:type`
+/// suffix on the key, e.g. `?age:int=30` or `?_id:oid=65a1...`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValueType {
+    String,
+    Int,
+    Long,
+    Double,
+    Bool,
+    Null,
+    ObjectId,
+    Date,
+}
+
+impl ValueType {
+    fn from_suffix(suffix: &str) -> Option<ValueType> {
+        match suffix {
+            "string" | "str" => Some(ValueType::String),
+            "int" => Some(ValueType::Int),
+            "long" => Some(ValueType::Long),
+            "double" | "number" => Some(ValueType::Double),
+            "bool" => Some(ValueType::Bool),
+            "null" => Some(ValueType::Null),
+            "oid" | "objectid" => Some(ValueType::ObjectId),
+            "date" => Some(ValueType::Date),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            ValueType::String => "string",
+            ValueType::Int => "int",
+            ValueType::Long => "long",
+            ValueType::Double => "double",
+            ValueType::Bool => "bool",
+            ValueType::Null => "null",
+            ValueType::ObjectId => "oid",
+            ValueType::Date => "date",
+        }
+    }
+}
+
+/// Splits `field:type` into the field name and its requested type. Keys
+/// whose suffix is not a known type are treated as plain field names.
+pub fn parse_key(key: &str) -> (&str, Option<ValueType>) {
+    if let Some((field, suffix)) = key.rsplit_once(':') {
+        if let Some(value_type) = ValueType::from_suffix(suffix) {
+            return (field, Some(value_type));
+        }
+    }
+    (key, None)
+}
+
+fn parse_date(raw: &str) -> Option<DateTime> {
+    if let Ok(date) = DateTime::parse_rfc3339_str(raw) {
+        return Some(date);
+    }
+    let date: NaiveDate = NaiveDate::parse_from_str(raw, "%Y-%m-%d").ok()?;
+    let millis: i64 = date.and_hms_opt(0, 0, 0)?.and_utc().timestamp_millis();
+    Some(DateTime::from_millis(millis))
+}
+
+fn parse_as(raw: &str, value_type: ValueType) -> Option<Bson> {
+    match value_type {
+        ValueType::String => Some(Bson::String(raw.to_string())),
+        ValueType::Int => raw.parse::<i32>().ok().map(Bson::Int32),
+        ValueType::Long => raw.parse::<i64>().ok().map(Bson::Int64),
+        ValueType::Double => raw.parse::<f64>().ok().map(Bson::Double),
+        ValueType::Bool => match raw {
+            "true" | "1" => Some(Bson::Boolean(true)),
+            "false" | "0" => Some(Bson::Boolean(false)),
+            _ => None,
+        },
+        ValueType::Null => Some(Bson::Null),
+        ValueType::ObjectId => ObjectId::parse_str(raw).ok().map(Bson::ObjectId),
+        ValueType::Date => parse_date(raw).map(Bson::DateTime),
+    }
+}
+
+/// Converts `raw` to the requested type, failing with a 400 when it does not parse.
+pub fn coerce(field: &str, raw: &str, value_type: ValueType) -> Result<Bson, ApiError> {
+    parse_as(raw, value_type).ok_or_else(|| {
+        ApiError::BadRequest(format!(
+            "Unable to parse `{raw}` as {} for field `{field}`",
+            value_type.name()
+        ))
+    })
+}
+
+/// Every typed reading of an untyped value, most specific first. Plain text
+/// has none, so it keeps matching as a string only.
+pub fn infer(raw: &str) -> Vec<Bson> {
+    let mut candidates: Vec<Bson> = Vec::new();
+    if raw == "null" {
+        candidates.push(Bson::Null);
+    }
+    if raw == "true" || raw == "false" {
+        candidates.push(Bson::Boolean(raw == "true"));
+    }
+    if let Ok(integer) = raw.parse::<i64>() {
+        candidates.push(Bson::Int64(integer));
+    } else if let Ok(float) = raw.parse::<f64>() {
+        if float.is_finite() {
+            candidates.push(Bson::Double(float));
+        }
+    }
+    if raw.len() == 24 {
+        if let Ok(id) = ObjectId::parse_str(raw) {
+            candidates.push(Bson::ObjectId(id));
+        }
+    }
+    if let Some(date) = parse_date(raw) {
+        candidates.push(Bson::DateTime(date));
+    }
+    candidates
+}
+
+/// Operators accepted when `allowed_operators` is not set in config.yaml.
+/// `$where`, `$function`, `$accumulator` and `$expr` are left out because
+/// they run server-side code or arbitrary aggregation expressions.
+pub const DEFAULT_OPERATORS: &[&str] = &[
+    "$eq",
+    "$ne",
+    "$gt",
+    "$gte",
+    "$lt",
+    "$lte",
+    "$in",
+    "$nin",
+    "$and",
+    "$or",
+    "$nor",
+    "$not",
+    "$exists",
+    "$type",
+    "$regex",
+    "$options",
+    "$all",
+    "$elemMatch",
+    "$size",
+    "$mod",
+];
+
+#[derive(Debug, Clone)]
+pub struct AllowedOperators(HashSet<String>);
+
+impl AllowedOperators {
+    pub fn from_config(config: &ConfigLayout) -> AllowedOperators {
+        let operators: HashSet<String> = match &config.allowed_operators {
+            Some(operators) => operators
+                .iter()
+                .map(|operator| format!("${}", operator.trim_start_matches('$')))
+                .collect(),
+            None => DEFAULT_OPERATORS
+                .iter()
+                .map(|operator| operator.to_string())
+                .collect(),
+        };
+        AllowedOperators(operators)
+    }
+
+    fn check_operator(&self, operator: &str) -> Result<(), ApiError> {
+        match self.0.contains(operator) {
+            true => Ok(()),
+            false => Err(ApiError::BadRequest(format!(
+                "The `{operator}` operator is not allowed"
+            ))),
+        }
+    }
+
+    /// Walks the whole filter and rejects any `$` key that is not allowed.
+    pub fn check(&self, filter: &Document) -> Result<(), ApiError> {
+        for (key, value) in filter.iter() {
+            if key.starts_with('$') {
+                self.check_operator(key)?;
+            }
+            self.check_value(value)?;
+        }
+        Ok(())
+    }
+
+    fn check_value(&self, value: &Bson) -> Result<(), ApiError> {
+        match value {
+            Bson::Document(document) => self.check(document),
+            Bson::Array(values) => values.iter().try_for_each(|value| self.check_value(value)),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Condition {
+    field: String,
+    operator: String,
+    value: Bson,
+}
+
+impl Condition {
+    fn new(field: &str, operator: &str, value: Bson) -> Condition {
+        Condition {
+            field: field.to_string(),
+            operator: operator.to_string(),
+            value,
+        }
+    }
+}
+
+/// Splits `field[op]` into the key and the operator name.
+fn split_operator(key: &str) -> (&str, Option<&str>) {
+    if let Some(rest) = key.strip_suffix(']') {
+        if let Some((key, operator)) = rest.rsplit_once('[') {
+            return (key, Some(operator));
+        }
+    }
+    (key, None)
+}
+
+/// The value itself followed by its typed readings.
+fn readings(raw: &str) -> Vec<Bson> {
+    let mut options: Vec<Bson> = vec![Bson::String(raw.to_string())];
+    options.extend(infer(raw));
+    options
+}
+
+fn single(field: &str, raw: &str, value_type: Option<ValueType>) -> Result<Bson, ApiError> {
+    match value_type {
+        Some(value_type) => coerce(field, raw, value_type),
+        None => Ok(infer(raw)
+            .into_iter()
+            .next()
+            .unwrap_or_else(|| Bson::String(raw.to_string()))),
+    }
+}
+
+fn list(field: &str, raw: &str, value_type: Option<ValueType>) -> Result<Vec<Bson>, ApiError> {
+    let mut values: Vec<Bson> = Vec::new();
+    for item in raw.split(',') {
+        match value_type {
+            Some(value_type) => values.push(coerce(field, item, value_type)?),
+            None => values.extend(readings(item)),
+        }
+    }
+    Ok(values)
+}
+
+/// Builds the condition for one `key=value` pair, where the key is
+/// `field[:type][[op]]`. An explicit type matches only that type, while an
+/// untyped value matches the string as well as every type it can be read
+/// as, so `?age=30` finds both `30` and `"30"`. Only the operator written
+/// by the client is checked against `allowed_operators`, not the `$eq`,
+/// `$in` or `$nin` used to implement it. A field starting with `$` would be
+/// read by MongoDB as an operator such as `$where`, so it is rejected.
+fn condition(key: &str, raw: &str, operators: &AllowedOperators) -> Result<Condition, ApiError> {
+    let (key, operator) = split_operator(key);
+    if let Some(operator) = operator {
+        operators.check_operator(&format!("${operator}"))?;
+    }
+    let (field, value_type) = parse_key(key);
+    if field.starts_with('$') {
+        return Err(ApiError::BadRequest(format!(
+            "`{field}` is not a field name, operators are written as `field[op]`"
+        )));
+    }
+    let condition: Condition = match operator.unwrap_or("eq") {
+        "eq" => match value_type {
+            Some(value_type) => Condition::new(field, "$eq", coerce(field, raw, value_type)?),
+            None if infer(raw).is_empty() => {
+                Condition::new(field, "$eq", Bson::String(raw.to_string()))
+            }
+            None => Condition::new(field, "$in", Bson::Array(readings(raw))),
+        },
+        "ne" => match value_type {
+            Some(value_type) => Condition::new(field, "$ne", coerce(field, raw, value_type)?),
+            None => Condition::new(field, "$nin", Bson::Array(readings(raw))),
+        },
+        operator @ ("gt" | "gte" | "lt" | "lte") => Condition::new(
+            field,
+            &format!("${operator}"),
+            single(field, raw, value_type)?,
+        ),
+        operator @ ("in" | "nin" | "all") => Condition::new(
+            field,
+            &format!("${operator}"),
+            Bson::Array(list(field, raw, value_type)?),
+        ),
+        "regex" => Condition::new(field, "$regex", Bson::String(raw.to_string())),
+        "options" => Condition::new(field, "$options", Bson::String(raw.to_string())),
+        "exists" => Condition::new(field, "$exists", coerce(field, raw, ValueType::Bool)?),
+        "size" => Condition::new(field, "$size", coerce(field, raw, ValueType::Int)?),
+        operator => {
+            return Err(ApiError::BadRequest(format!(
+                "Unknown operator `{operator}` for field `{field}`"
+            )))
+        }
+    };
+    Ok(condition)
+}
+
+/// Combines conditions on the same field into one operator document. A
+/// repeated operator on a field goes to `$and` instead of overwriting it.
+fn merge(conditions: Vec<Condition>) -> Document {
+    let mut query: Document = doc! {};
+    let mut repeated: Vec<Bson> = Vec::new();
+    for Condition {
+        field,
+        operator,
+        value,
+    } in conditions
+    {
+        match query.get_mut(&field) {
+            Some(Bson::Document(operators)) if !operators.contains_key(&operator) => {
+                operators.insert(operator, value);
+            }
+            Some(_) => repeated.push(Bson::Document(doc! { field: { operator: value } })),
+            None => {
+                query.insert(field, doc! { operator: value });
+            }
+        }
+    }
+    if !repeated.is_empty() {
+        query.insert("$and", repeated);
+    }
+    query
+}
+
+/// Turns the query string of a request into a MongoDB filter document.
+/// Keys of the form `or.<group>.<field>` are ANDed within their group and
+/// the groups are combined with `$or`, which must then be allowed.
+pub fn search_document(
+    searches: &HashMap<String, String>,
+    operators: &AllowedOperators,
+) -> Result<Document, ApiError> {
+    let mut conditions: Vec<Condition> = Vec::new();
+    let mut groups: BTreeMap<&str, Vec<Condition>> = BTreeMap::new();
+    for (key, value) in searches.iter() {
+        match key
+            .strip_prefix("or.")
+            .and_then(|rest| rest.split_once('.'))
+        {
+            Some((group, key)) => groups
+                .entry(group)
+                .or_default()
+                .push(condition(key, value, operators)?),
+            None => conditions.push(condition(key, value, operators)?),
+        }
+    }
+    let mut query: Document = merge(conditions);
+    if !groups.is_empty() {
+        operators.check_operator("$or")?;
+        let alternatives: Vec<Document> = groups.into_values().map(merge).collect();
+        query.insert("$or", alternatives);
+    }
+    Ok(query)
+}
+
+/// Converts a JSON filter, which may use extended JSON such as
+/// `{"$oid": "..."}`, into a filter document.
+pub fn json_filter(filter: &Value, operators: &AllowedOperators) -> Result<Document, ApiError> {
+    let query: Document = match Bson::try_from(filter.clone()) {
+        Ok(Bson::Document(query)) => query,
+        Ok(_) => {
+            return Err(ApiError::BadRequest(String::from(
+                "The `filter` tag should be an object",
+            )))
+        }
+        Err(e) => return Err(ApiError::BadRequest(format!("Invalid filter: {e}"))),
+    };
+    operators.check(&query)?;
+    Ok(query)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::query_string;
+
+    fn defaults() -> AllowedOperators {
+        AllowedOperators(
+            DEFAULT_OPERATORS
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+        )
+    }
+
+    fn only(operators: &[&str]) -> AllowedOperators {
+        AllowedOperators(operators.iter().map(|name| name.to_string()).collect())
+    }
+
+    fn search(pairs: &[(&str, &str)], operators: &AllowedOperators) -> Result<Document, ApiError> {
+        search_document(&query_string(pairs), operators)
+    }
+
+    #[test]
+    fn untyped_values_match_every_reading() {
+        let query: Document = search(&[("age", "30")], &defaults()).unwrap();
+        assert_eq!(query, doc! { "age": { "$in": ["30", 30i64] } });
+        let query: Document = search(&[("name", "bob")], &defaults()).unwrap();
+        assert_eq!(query, doc! { "name": { "$eq": "bob" } });
+        let query: Document = search(&[("age[ne]", "30")], &defaults()).unwrap();
+        assert_eq!(query, doc! { "age": { "$nin": ["30", 30i64] } });
+    }
+
+    #[test]
+    fn typed_values_and_operators() {
+        let query: Document = search(&[("age:int[gte]", "18")], &defaults()).unwrap();
+        assert_eq!(query, doc! { "age": { "$gte": 18 } });
+        let query: Document = search(&[("tags:string[in]", "a,b")], &defaults()).unwrap();
+        assert_eq!(query, doc! { "tags": { "$in": ["a", "b"] } });
+        assert!(search(&[("age:int", "old")], &defaults()).is_err());
+        assert!(search(&[("age[near]", "1")], &defaults()).is_err());
+    }
+
+    #[test]
+    fn conditions_on_a_field_are_merged() {
+        let conditions: Vec<Condition> = vec![
+            Condition::new("age", "$gte", Bson::Int32(18)),
+            Condition::new("age", "$lt", Bson::Int32(65)),
+            Condition::new("age", "$gte", Bson::Int32(21)),
+        ];
+        assert_eq!(
+            merge(conditions),
+            doc! {
+                "age": { "$gte": 18, "$lt": 65 },
+                "$and": [{ "age": { "$gte": 21 } }],
+            }
+        );
+    }
+
+    #[test]
+    fn or_groups() {
+        let query: Document = search(
+            &[
+                ("or.a.name", "bob"),
+                ("or.b.name", "alice"),
+                ("active:bool", "true"),
+            ],
+            &defaults(),
+        )
+        .unwrap();
+        assert_eq!(
+            query,
+            doc! {
+                "active": { "$eq": true },
+                "$or": [{ "name": { "$eq": "bob" } }, { "name": { "$eq": "alice" } }],
+            }
+        );
+        assert!(search(&[("or.a.name", "bob")], &only(&["$eq"])).is_err());
+    }
+
+    #[test]
+    fn only_client_operators_are_checked() {
+        let regex: AllowedOperators = only(&["$regex"]);
+        assert!(search(&[("name", "bob")], &regex).is_ok());
+        assert!(search(&[("age", "30")], &regex).is_ok());
+        assert!(search(&[("name[regex]", "^b")], &regex).is_ok());
+        assert!(search(&[("age[gt]", "30")], &regex).is_err());
+        let ne: AllowedOperators = only(&["$ne"]);
+        assert!(search(&[("age[ne]", "30")], &ne).is_ok());
+        assert!(search(&[("age[nin]", "30")], &ne).is_err());
+    }
+
+    #[test]
+    fn fields_cannot_be_operators() {
+        let everything: AllowedOperators = only(&["$eq", "$in", "$or", "$where", "$expr"]);
+        assert!(search(&[("$where", "1")], &everything).is_err());
+        assert!(search(&[("$expr", "1")], &everything).is_err());
+        assert!(search(&[("$expr:bool[ne]", "true")], &everything).is_err());
+        assert!(search(&[("or.a.$where", "1")], &everything).is_err());
+    }
+
+    #[test]
+    fn json_filters_are_checked_deeply() {
+        let filter: Value = serde_json::json!({ "$or": [{ "$where": "sleep(1000)" }] });
+        assert!(json_filter(&filter, &defaults()).is_err());
+        let filter: Value = serde_json::json!({ "age": { "$gt": 3 } });
+        assert!(json_filter(&filter, &defaults()).is_ok());
+    }
+}
@@ -1,2 +1,5 @@
+pub mod expose;
+pub mod filter;
 mod mongoose;
+mod page;
 pub mod routes;
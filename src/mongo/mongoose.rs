@@ -1,380 +1,343 @@
-use actix_web::{web, HttpResponse, Responder};
-use bson::{doc, Document};
+use super::{
+    expose::Expose,
+    filter::{json_filter, search_document, AllowedOperators},
+    page::Page,
+};
+use crate::{
+    error::ApiError,
+    stream::{ndjson, wants_ndjson},
+};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use bson::{doc, Bson, Document};
 use futures::TryStreamExt;
-use mongodb::{options::FindOptions, Cursor};
-use serde_json::Value;
+use mongodb::{
+    results::{DeleteResult, InsertManyResult, InsertOneResult, UpdateResult},
+    Collection, Cursor,
+};
+use serde_json::{json, Value};
 use std::collections::HashMap;
 
 pub async fn index() -> impl Responder {
     "Hello From MongoDB!"
 }
 
+fn body_document(body: &Value, expected: &str) -> Result<Document, ApiError> {
+    let value: &Value = body
+        .get("value")
+        .ok_or_else(|| ApiError::missing_field("value", expected))?;
+    Ok(bson::to_document(value)?)
+}
+
+fn id_to_string(id: &Bson) -> String {
+    match id {
+        Bson::ObjectId(id) => id.to_hex(),
+        Bson::String(id) => id.clone(),
+        id => id.to_string(),
+    }
+}
+
+fn update_response(result: UpdateResult) -> HttpResponse {
+    HttpResponse::Ok().json(doc! {
+        "matched_count": result.matched_count.to_string(),
+        "modified_count": result.modified_count.to_string(),
+        "upserted_id": result.upserted_id.unwrap_or_default().to_string()
+    })
+}
+
 pub async fn find_one(
     params: web::Path<(String, String)>,
     searches: web::Query<HashMap<String, String>>,
+    operators: web::Data<AllowedOperators>,
     client: web::Data<mongodb::Client>,
-) -> impl Responder {
+) -> Result<HttpResponse, ApiError> {
     let db: mongodb::Database = client.database(&params.0);
     let collection: mongodb::Collection<Document> = db.collection::<Document>(&params.1);
-    let mut query: Document = doc! {};
-    for (key, value) in searches.iter() {
-        query.insert(key, value);
+    let query: Document = search_document(&searches, &operators)?;
+    match collection.find_one(query, None).await? {
+        Some(document) => Ok(HttpResponse::Ok().json(document)),
+        None => Err(ApiError::NotFound(String::from(
+            "No matching document found",
+        ))),
+    }
+}
+
+/// Runs one page of a find and wraps it as `{data, next_cursor, total?}`,
+/// or streams every match as NDJSON when the client asked for it.
+async fn page_response(
+    req: &HttpRequest,
+    collection: &Collection<Document>,
+    query: Document,
+    page: Page,
+) -> Result<HttpResponse, ApiError> {
+    if wants_ndjson(req) {
+        let cursor: Cursor<Document> = collection
+            .find(page.filter(query), page.stream_options())
+            .await?;
+        return Ok(ndjson(cursor.map_ok(move |mut document| {
+            page.strip(&mut document);
+            document
+        })));
+    }
+    let total: Option<u64> = match page.count {
+        true => Some(collection.count_documents(query.clone(), None).await?),
+        false => None,
+    };
+    let mut cursor: Cursor<Document> = collection.find(page.filter(query), page.options()).await?;
+    let mut documents: Vec<Document> = Vec::new();
+    while let Some(document) = cursor.try_next().await? {
+        documents.push(document)
     }
-    match collection.find_one(query, None).await {
-        Ok(result) => match result {
-            Some(document) => HttpResponse::Ok().json(document),
-            None => HttpResponse::NotFound().body("No matching document found"),
-        },
-        Err(e) => HttpResponse::InternalServerError().body(format!("Error: {}", e)),
+    let (documents, next_cursor) = page.finish(documents)?;
+    let mut response: Value = json!({ "data": documents, "next_cursor": next_cursor });
+    if let Some(total) = total {
+        response["total"] = json!(total);
     }
+    Ok(HttpResponse::Ok().json(response))
 }
 
 pub async fn find_many(
+    req: HttpRequest,
     params: web::Path<(String, String)>,
     searches: web::Query<HashMap<String, String>>,
+    operators: web::Data<AllowedOperators>,
     client: web::Data<mongodb::Client>,
-) -> impl Responder {
+) -> Result<HttpResponse, ApiError> {
     let db: mongodb::Database = client.database(&params.0);
     let collection: mongodb::Collection<Document> = db.collection::<Document>(&params.1);
-    let mut query: Document = doc! {};
-    for (key, value) in searches.iter() {
-        query.insert(key, value);
-    }
-    let options: FindOptions = FindOptions::builder().build();
-    let cursor: Result<Cursor<Document>, mongodb::error::Error> =
-        collection.find(query, options).await;
-    let mut response: Vec<Document> = Vec::new();
-    match cursor {
-        Ok(mut cursor) => {
-            while let Ok(Some(doc)) = &cursor.try_next().await {
-                response.push(doc.clone())
-            }
-            HttpResponse::Ok().json(response)
-        }
-        Err(e) => HttpResponse::InternalServerError().body(format!("Error: {}", e)),
-    }
+    let mut searches: HashMap<String, String> = searches.into_inner();
+    let page: Page = Page::from_query(&mut searches)?;
+    let query: Document = search_document(&searches, &operators)?;
+    page_response(&req, &collection, query, page).await
+}
+
+pub async fn query(
+    req: HttpRequest,
+    params: web::Path<(String, String)>,
+    body: web::Json<Value>,
+    client: web::Data<mongodb::Client>,
+    operators: web::Data<AllowedOperators>,
+) -> Result<HttpResponse, ApiError> {
+    let db: mongodb::Database = client.database(&params.0);
+    let collection: mongodb::Collection<Document> = db.collection::<Document>(&params.1);
+    let filter: &Value = body
+        .get("filter")
+        .ok_or_else(|| ApiError::missing_field("filter", "the filter document"))?;
+    let query: Document = json_filter(filter, &operators)?;
+    let page: Page = Page::from_body(&body)?;
+    page_response(&req, &collection, query, page).await
 }
 
 pub async fn insert_one(
     params: web::Path<(String, String)>,
     body: web::Json<Value>,
     client: web::Data<mongodb::Client>,
-) -> impl Responder {
+) -> Result<HttpResponse, ApiError> {
     let db: mongodb::Database = client.database(&params.0);
     let collection: mongodb::Collection<Document> = db.collection::<Document>(&params.1);
-    let data_value_to_be_inserted: &Value = body.get("value").unwrap_or_else(|| {
-        panic!("The body should have a `value` tag with the data to be inserted in the collection")
-    });
-    let data_to_be_inserted: Document = bson::to_document(data_value_to_be_inserted)
-        .map_err(|e| HttpResponse::InternalServerError().body(format!("Error: {}", e)))
-        .unwrap();
-    match collection.insert_one(data_to_be_inserted, None).await {
-        Ok(result) => {
-            let inserted_id: String = result
-                .inserted_id
-                .as_object_id()
-                .unwrap()
-                .to_hex()
-                .to_string();
-            HttpResponse::Ok().json(doc! {
-                "inserted_id": inserted_id
-            })
-        }
-        Err(e) => HttpResponse::InternalServerError().body(format!("Error: {}", e)),
-    }
+    let data_to_be_inserted: Document =
+        body_document(&body, "the data to be inserted in the collection")?;
+    let result: InsertOneResult = collection.insert_one(data_to_be_inserted, None).await?;
+    Ok(HttpResponse::Ok().json(doc! {
+        "inserted_id": id_to_string(&result.inserted_id)
+    }))
 }
 
 pub async fn insert_many(
     params: web::Path<(String, String)>,
     client: web::Data<mongodb::Client>,
     body: web::Json<Value>,
-) -> impl Responder {
+) -> Result<HttpResponse, ApiError> {
     let db: mongodb::Database = client.database(&params.0);
     let collections: mongodb::Collection<Document> = db.collection::<Document>(&params.1);
     let values_to_be_inserted: Vec<Document> = body
         .get("values")
-        .unwrap_or_else(|| {
-            panic!("The body should have a `values` tag with the data to be inserted in the collection")
-        })
-        .as_array()
-        .unwrap()
+        .and_then(Value::as_array)
+        .ok_or_else(|| {
+            ApiError::missing_field(
+                "values",
+                "an array of the data to be inserted in the collection",
+            )
+        })?
+        .iter()
+        .map(bson::to_document)
+        .collect::<Result<_, _>>()?;
+    let result: InsertManyResult = collections.insert_many(values_to_be_inserted, None).await?;
+    let inserted_ids: Vec<String> = result
+        .inserted_ids
         .iter()
-        .map(|value| bson::to_document(value).unwrap())
+        .map(|(key, value)| format!("{}: {}", key, id_to_string(value)))
         .collect();
-
-    match collections.insert_many(values_to_be_inserted, None).await {
-        Ok(result) => {
-            let inserted_ids: Vec<String> = result
-                .inserted_ids
-                .iter()
-                .map(|(key, value)| {
-                    format!(
-                        "{}: {}",
-                        key,
-                        value.as_object_id().unwrap().to_hex().to_string()
-                    )
-                })
-                .collect();
-            HttpResponse::Ok().json(doc! {
-                "inserted_ids": inserted_ids
-            })
-        }
-        Err(e) => HttpResponse::InternalServerError().body(format!("Error: {}", e)),
-    }
+    Ok(HttpResponse::Ok().json(doc! {
+        "inserted_ids": inserted_ids
+    }))
 }
 
 pub async fn delete_one(
     params: web::Path<(String, String)>,
     client: web::Data<mongodb::Client>,
     searches: web::Query<HashMap<String, String>>,
-) -> impl Responder {
+    operators: web::Data<AllowedOperators>,
+) -> Result<HttpResponse, ApiError> {
     let db: mongodb::Database = client.database(&params.0);
     let collection: mongodb::Collection<Document> = db.collection::<Document>(&params.1);
-    let mut query: Document = doc! {};
-    for (key, value) in searches.iter() {
-        query.insert(key, value);
-    }
-    match collection.delete_one(query, None).await {
-        Ok(result) => HttpResponse::Ok().json(doc! {
-            "deleted_count": result.deleted_count.to_string()
-        }),
-        Err(e) => HttpResponse::InternalServerError().body(format!("Error: {}", e)),
-    }
+    let query: Document = search_document(&searches, &operators)?;
+    let result: DeleteResult = collection.delete_one(query, None).await?;
+    Ok(HttpResponse::Ok().json(doc! {
+        "deleted_count": result.deleted_count.to_string()
+    }))
 }
 
 pub async fn delete_many(
     params: web::Path<(String, String)>,
     client: web::Data<mongodb::Client>,
     searches: web::Query<HashMap<String, String>>,
-) -> impl Responder {
+    operators: web::Data<AllowedOperators>,
+) -> Result<HttpResponse, ApiError> {
     let db: mongodb::Database = client.database(&params.0);
     let collection: mongodb::Collection<Document> = db.collection::<Document>(&params.1);
-    let mut query: Document = doc! {};
-    for (key, value) in searches.iter() {
-        query.insert(key, value);
-    }
-    match collection.delete_many(query, None).await {
-        Ok(result) => HttpResponse::Ok().json(doc! {
-            "deleted_count": result.deleted_count.to_string()
-        }),
-        Err(e) => HttpResponse::InternalServerError().body(format!("Error: {}", e)),
-    }
+    let query: Document = search_document(&searches, &operators)?;
+    let result: DeleteResult = collection.delete_many(query, None).await?;
+    Ok(HttpResponse::Ok().json(doc! {
+        "deleted_count": result.deleted_count.to_string()
+    }))
 }
 
 pub async fn show_collections_in_a_database(
     params: web::Path<String>,
     client: web::Data<mongodb::Client>,
-) -> impl Responder {
+    expose: web::Data<Expose>,
+) -> Result<HttpResponse, ApiError> {
     let db: mongodb::Database = client.database(&params);
-    let collections: Result<Vec<String>, mongodb::error::Error> =
-        db.list_collection_names(None).await;
-    match collections {
-        Ok(collections) => HttpResponse::Ok().json(collections),
-        Err(e) => HttpResponse::InternalServerError().body(format!("Error: {e}")),
-    }
+    let mut collections: Vec<String> = db.list_collection_names(None).await?;
+    collections.retain(|collection| expose.collection(&params, collection));
+    Ok(HttpResponse::Ok().json(collections))
 }
 
-pub async fn get_all_databases(client: web::Data<mongodb::Client>) -> impl Responder {
-    let databases: Result<Vec<String>, mongodb::error::Error> =
-        client.list_database_names(None, None).await;
-    match databases {
-        Ok(database) => HttpResponse::Ok().json(database),
-        Err(e) => HttpResponse::InternalServerError().body(format!("Error: {}", e)),
-    }
+pub async fn get_all_databases(
+    client: web::Data<mongodb::Client>,
+    expose: web::Data<Expose>,
+) -> Result<HttpResponse, ApiError> {
+    let mut databases: Vec<String> = client.list_database_names(None, None).await?;
+    databases.retain(|database| expose.database(database));
+    Ok(HttpResponse::Ok().json(databases))
 }
 
 pub async fn drop_database(
     params: web::Path<String>,
     client: web::Data<mongodb::Client>,
-) -> impl Responder {
+) -> Result<HttpResponse, ApiError> {
     let db: mongodb::Database = client.database(&params);
-    match db.drop(None).await {
-        Ok(_) => HttpResponse::Ok().body(format!("Database {} dropped", params)),
-        Err(e) => HttpResponse::InternalServerError().body(format!("Error: {}", e)),
-    }
+    db.drop(None).await?;
+    Ok(HttpResponse::Ok().body(format!("Database {} dropped", params)))
 }
 
 pub async fn drop_collection(
     params: web::Path<(String, String)>,
     client: web::Data<mongodb::Client>,
-) -> impl Responder {
+) -> Result<HttpResponse, ApiError> {
     let db: mongodb::Database = client.database(&params.0);
-    match db.collection::<Document>(&params.1).drop(None).await {
-        Ok(_) => HttpResponse::Ok().body(format!("Collection {} dropped", params.1)),
-        Err(e) => HttpResponse::InternalServerError().body(format!("Error: {}", e)),
-    }
+    db.collection::<Document>(&params.1).drop(None).await?;
+    Ok(HttpResponse::Ok().body(format!("Collection {} dropped", params.1)))
 }
 
 pub async fn update_one(
     params: web::Path<(String, String)>,
     client: web::Data<mongodb::Client>,
     searches: web::Query<HashMap<String, String>>,
+    operators: web::Data<AllowedOperators>,
     body: web::Json<Value>,
-) -> impl Responder {
+) -> Result<HttpResponse, ApiError> {
     let db: mongodb::Database = client.database(&params.0);
     let collection: mongodb::Collection<Document> = db.collection::<Document>(&params.1);
-    let mut query: Document = doc! {};
-    for (key, value) in searches.iter() {
-        query.insert(key, value);
-    }
-    let data_value_to_be_updated: &Value = body.get("value").unwrap_or_else(|| {
-        panic!("The body should have a `value` tag with the data to be inserted in the collection")
-    });
-    let data_to_be_updated: Document = bson::to_document(data_value_to_be_updated)
-        .map_err(|e| HttpResponse::InternalServerError().body(format!("Error: {}", e)))
-        .unwrap();
+    let query: Document = search_document(&searches, &operators)?;
+    let data_to_be_updated: Document = body_document(&body, "the fields to be updated")?;
     let update_document: Document = doc! {"$set": data_to_be_updated};
-    match collection.update_one(query, update_document, None).await {
-        Ok(result) => HttpResponse::Ok().json(doc! {
-            "matched_count": result.matched_count.to_string(),
-            "modified_count": result.modified_count.to_string(),
-            "upserted_id": result.upserted_id.unwrap_or_default().to_string()
-        }),
-        Err(e) => HttpResponse::InternalServerError().body(format!("Error: {}", e)),
-    }
+    let result: UpdateResult = collection.update_one(query, update_document, None).await?;
+    Ok(update_response(result))
 }
 
 pub async fn update_many(
     params: web::Path<(String, String)>,
     client: web::Data<mongodb::Client>,
     searches: web::Query<HashMap<String, String>>,
+    operators: web::Data<AllowedOperators>,
     body: web::Json<Value>,
-) -> impl Responder {
+) -> Result<HttpResponse, ApiError> {
     let db: mongodb::Database = client.database(&params.0);
     let collection: mongodb::Collection<Document> = db.collection::<Document>(&params.1);
-    let mut query: Document = doc! {};
-    for (key, value) in searches.iter() {
-        query.insert(key, value);
-    }
-    let data_value_to_be_updated: &Value = body.get("value").unwrap_or_else(|| {
-        panic!("The body should have a `value` tag with the data to be inserted in the collection")
-    });
-    // println!("{:?}", data_value_to_be_updated);
-    let data_to_be_updated: Document = bson::to_document(data_value_to_be_updated)
-        .map_err(|e| HttpResponse::InternalServerError().body(format!("Error: {}", e)))
-        .unwrap();
-    // println!("{:?}", data_to_be_updated);
+    let query: Document = search_document(&searches, &operators)?;
+    let data_to_be_updated: Document = body_document(&body, "the fields to be updated")?;
     let update_document: Document = doc! {"$set": data_to_be_updated};
-    match collection.update_many(query, update_document, None).await {
-        Ok(result) => HttpResponse::Ok().json(doc! {
-            "matched_count": result.matched_count.to_string(),
-            "modified_count": result.modified_count.to_string(),
-            "upserted_id": result.upserted_id.unwrap_or_default().to_string()
-        }),
-        Err(e) => HttpResponse::InternalServerError().body(format!("Error: {}", e)),
-    }
+    let result: UpdateResult = collection.update_many(query, update_document, None).await?;
+    Ok(update_response(result))
 }
 
 pub async fn rename_field(
     params: web::Path<(String, String)>,
     client: web::Data<mongodb::Client>,
     searches: web::Query<HashMap<String, String>>,
+    operators: web::Data<AllowedOperators>,
     body: web::Json<Value>,
-) -> impl Responder {
+) -> Result<HttpResponse, ApiError> {
     let db: mongodb::Database = client.database(&params.0);
     let collection: mongodb::Collection<Document> = db.collection::<Document>(&params.1);
-    let data_value_to_be_updated: &Value = body.get("value").unwrap_or_else(|| {
-        panic!("The body should have a `value` tag with the data to be inserted in the collection")
-    });
-    let mut query: Document = doc! {};
-    for (key, value) in searches.iter() {
-        query.insert(key, value);
-    }
-    let data_to_be_updated: Document = bson::to_document(data_value_to_be_updated)
-        .map_err(|e| HttpResponse::InternalServerError().body(format!("Error: {}", e)))
-        .unwrap();
+    let data_to_be_updated: Document =
+        body_document(&body, "a mapping of the old field names to the new ones")?;
+    let query: Document = search_document(&searches, &operators)?;
     let update_document: Document = doc! {"$rename": data_to_be_updated};
-    match collection.update_many(query, update_document, None).await {
-        Ok(result) => HttpResponse::Ok().json(doc! {
-            "matched_count": result.matched_count.to_string(),
-            "modified_count": result.modified_count.to_string(),
-            "upserted_id": result.upserted_id.unwrap_or_default().to_string()
-        }),
-        Err(e) => HttpResponse::InternalServerError().body(format!("Error: {}", e)),
-    }
+    let result: UpdateResult = collection.update_many(query, update_document, None).await?;
+    Ok(update_response(result))
 }
 
 // Can we pop from only selected fields ?
 pub async fn pop_last(
     params: web::Path<(String, String, String)>,
     client: web::Data<mongodb::Client>,
-) -> impl Responder {
+) -> Result<HttpResponse, ApiError> {
     let db: mongodb::Database = client.database(&params.0);
     let collection: mongodb::Collection<Document> = db.collection::<Document>(&params.1);
     let query: Document = doc! {"$pop": {&params.2: 1}};
-    match collection.update_many(doc! {}, query, None).await {
-        Ok(result) => HttpResponse::Ok().json(doc! {
-            "matched_count": result.matched_count.to_string(),
-            "modified_count": result.modified_count.to_string(),
-            "upserted_id": result.upserted_id.unwrap_or_default().to_string()
-        }),
-        Err(e) => HttpResponse::InternalServerError().body(format!("Error: {}", e)),
-    }
+    let result: UpdateResult = collection.update_many(doc! {}, query, None).await?;
+    Ok(update_response(result))
 }
 
 pub async fn pop_first(
     params: web::Path<(String, String, String)>,
     client: web::Data<mongodb::Client>,
-) -> impl Responder {
+) -> Result<HttpResponse, ApiError> {
     let db: mongodb::Database = client.database(&params.0);
     let collection: mongodb::Collection<Document> = db.collection::<Document>(&params.1);
     let query: Document = doc! {"$pop": {&params.2: -1}};
-    match collection.update_many(doc! {}, query, None).await {
-        Ok(result) => HttpResponse::Ok().json(doc! {
-            "matched_count": result.matched_count.to_string(),
-            "modified_count": result.modified_count.to_string(),
-            "upserted_id": result.upserted_id.unwrap_or_default().to_string()
-        }),
-        Err(e) => HttpResponse::InternalServerError().body(format!("Error: {}", e)),
-    }
+    let result: UpdateResult = collection.update_many(doc! {}, query, None).await?;
+    Ok(update_response(result))
 }
 
 pub async fn push_element(
     params: web::Path<(String, String, String)>,
     client: web::Data<mongodb::Client>,
     body: web::Json<Value>,
-) -> impl Responder {
+) -> Result<HttpResponse, ApiError> {
     let db: mongodb::Database = client.database(&params.0);
     let collection: mongodb::Collection<Document> = db.collection::<Document>(&params.1);
-    let data_value_to_be_updated: &Value = body.get("value").unwrap_or_else(|| {
-        panic!("The body should have a `value` tag with the data to be inserted in the collection")
-    });
-    let data_to_be_updated: Document = bson::to_document(data_value_to_be_updated)
-        .map_err(|e| HttpResponse::InternalServerError().body(format!("Error: {}", e)))
-        .unwrap();
+    let data_to_be_updated: Document =
+        body_document(&body, "the element to be pushed to the array")?;
     let query: Document = doc! {"$push" : {&params.2 : data_to_be_updated}};
-    match collection.update_many(doc! {}, query, None).await {
-        Ok(result) => HttpResponse::Ok().json(doc! {
-            "matched_count": result.matched_count.to_string(),
-            "modified_count": result.modified_count.to_string(),
-            "upserted_id": result.upserted_id.unwrap_or_default().to_string()
-        }),
-        Err(e) => HttpResponse::InternalServerError().body(format!("Error: {}", e)),
-    }
+    let result: UpdateResult = collection.update_many(doc! {}, query, None).await?;
+    Ok(update_response(result))
 }
 
 pub async fn increment(
     params: web::Path<(String, String)>,
     client: web::Data<mongodb::Client>,
     body: web::Json<Value>,
-) -> impl Responder {
+) -> Result<HttpResponse, ApiError> {
     let db: mongodb::Database = client.database(&params.0);
     let collection: mongodb::Collection<Document> = db.collection::<Document>(&params.1);
-    let data_value_to_be_updated: &Value = body.get("value").unwrap_or_else(|| {
-        panic!("The body should have a `value` tag with the data to be inserted in the collection")
-    });
-    let data_to_be_updated: Document = bson::to_document(data_value_to_be_updated)
-        .map_err(|e| HttpResponse::InternalServerError().body(format!("Error: {}", e)))
-        .unwrap();
+    let data_to_be_updated: Document =
+        body_document(&body, "the fields and amounts to be incremented")?;
     let query: Document = doc! {"$inc" : data_to_be_updated};
-    match collection.update_many(doc! {}, query, None).await {
-        Ok(result) => HttpResponse::Ok().json(doc! {
-            "matched_count": result.matched_count.to_string(),
-            "modified_count": result.modified_count.to_string(),
-            "upserted_id": result.upserted_id.unwrap_or_default().to_string()
-        }),
-        Err(e) => HttpResponse::InternalServerError().body(format!("Error: {}", e)),
-    }
+    let result: UpdateResult = collection.update_many(doc! {}, query, None).await?;
+    Ok(update_response(result))
 }
 
 // pub async fn pull_elements(
@@ -383,6 +346,6 @@ pub async fn increment(
 // ) -> impl Responder {
 //     let db: mongodb::Database = client.database(&params.0);
 //     let collection: mongodb::Collection<Document> = db.collection::<Document>(&params.1);
-    
+
 //     todo!()
 // }
@@ -0,0 +1,547 @@
+use crate::error::ApiError;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use bson::{doc, Bson, Document};
+use mongodb::options::FindOptions;
+use serde_json::Value;
+use std::collections::HashMap;
+
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+
+/// `$type` aliases in the order MongoDB sorts values of different types.
+/// Numbers of every width compare with each other, and `$gt`/`$lt` only
+/// match within one of these groups. Arrays sort by one of their elements
+/// and cannot be paged by.
+const TYPE_ORDER: [&[&str]; 9] = [
+    &["number"],
+    &["string", "symbol"],
+    &["object"],
+    &["binData"],
+    &["objectId"],
+    &["bool"],
+    &["date"],
+    &["timestamp"],
+    &["regex"],
+];
+
+/// Query-string parameters that control paging rather than filtering.
+const PAGE_PARAMETERS: [&str; 6] = ["limit", "skip", "after", "sort", "fields", "count"];
+
+/// Paging options of a `find_many` or `query` request.
+///
+/// The sort always ends on `_id` so every document has a unique position,
+/// which is what the opaque `after` cursor records.
+#[derive(Debug)]
+pub struct Page {
+    // Only set when the request gave one; pages default to `DEFAULT_LIMIT`
+    // while streams run to the end of the cursor.
+    limit: Option<i64>,
+    skip: u64,
+    sort: Vec<(String, i32)>,
+    after: Option<Vec<Bson>>,
+    projection: Option<Document>,
+    // Sort fields fetched only to build the next cursor.
+    hidden: Vec<String>,
+    pub count: bool,
+}
+
+impl Page {
+    /// Takes the paging parameters out of `searches`, leaving only filters.
+    pub fn from_query(searches: &mut HashMap<String, String>) -> Result<Page, ApiError> {
+        let mut parameters: HashMap<&str, String> = HashMap::new();
+        for name in PAGE_PARAMETERS {
+            if let Some(value) = searches.remove(name) {
+                parameters.insert(name, value);
+            }
+        }
+        Page::parse(&parameters)
+    }
+
+    /// Reads the paging tags of a JSON body. `sort` and `fields` may be
+    /// given either as a comma separated string or as an array.
+    pub fn from_body(body: &Value) -> Result<Page, ApiError> {
+        let mut parameters: HashMap<&str, String> = HashMap::new();
+        for name in PAGE_PARAMETERS {
+            let value: Option<String> = match body.get(name) {
+                None | Some(Value::Null) => None,
+                Some(Value::String(value)) => Some(value.clone()),
+                Some(Value::Array(values)) => Some(
+                    values
+                        .iter()
+                        .map(|value| {
+                            value
+                                .as_str()
+                                .map(String::from)
+                                .unwrap_or(value.to_string())
+                        })
+                        .collect::<Vec<String>>()
+                        .join(","),
+                ),
+                Some(value) => Some(value.to_string()),
+            };
+            if let Some(value) = value {
+                parameters.insert(name, value);
+            }
+        }
+        Page::parse(&parameters)
+    }
+
+    fn parse(parameters: &HashMap<&str, String>) -> Result<Page, ApiError> {
+        let limit: Option<i64> = match parameters.get("limit") {
+            Some(limit) => match limit.parse::<i64>() {
+                Ok(limit) if (1..=MAX_LIMIT).contains(&limit) => Some(limit),
+                _ => {
+                    return Err(ApiError::BadRequest(format!(
+                        "`limit` should be a number between 1 and {MAX_LIMIT}"
+                    )))
+                }
+            },
+            None => None,
+        };
+        let skip: u64 = match parameters.get("skip") {
+            Some(skip) => skip.parse::<u64>().map_err(|_| {
+                ApiError::BadRequest(String::from("`skip` should be a positive number"))
+            })?,
+            None => 0,
+        };
+        let count: bool = match parameters.get("count").map(String::as_str) {
+            Some("true") | Some("1") => true,
+            Some("false") | Some("0") | None => false,
+            Some(_) => {
+                return Err(ApiError::BadRequest(String::from(
+                    "`count` should be true or false",
+                )))
+            }
+        };
+
+        let mut sort: Vec<(String, i32)> = Vec::new();
+        for field in list(parameters.get("sort")) {
+            let (field, direction) = match field.strip_prefix('-') {
+                Some(field) => (field, -1),
+                None => (field.strip_prefix('+').unwrap_or(field), 1),
+            };
+            if sort.iter().any(|(known, _)| known == field) {
+                return Err(ApiError::BadRequest(format!(
+                    "`{field}` appears more than once in `sort`"
+                )));
+            }
+            sort.push((field.to_string(), direction));
+        }
+        if !sort.iter().any(|(field, _)| field == "_id") {
+            sort.push((String::from("_id"), 1));
+        }
+
+        let (projection, hidden) = projection(list(parameters.get("fields")), &sort);
+        let mut page: Page = Page {
+            limit,
+            skip,
+            sort,
+            after: None,
+            projection,
+            hidden,
+            count,
+        };
+        if let Some(token) = parameters.get("after") {
+            page.after = Some(page.decode_cursor(token)?);
+        }
+        Ok(page)
+    }
+
+    fn sort_document(&self) -> Document {
+        self.sort
+            .iter()
+            .map(|(field, direction)| (field.clone(), Bson::Int32(*direction)))
+            .collect()
+    }
+
+    fn page_size(&self) -> i64 {
+        self.limit.unwrap_or(DEFAULT_LIMIT)
+    }
+
+    pub fn options(&self) -> FindOptions {
+        FindOptions::builder()
+            // One extra document tells whether there is a next page.
+            .limit(self.page_size() + 1)
+            .skip(self.skip)
+            .sort(self.sort_document())
+            .projection(self.projection.clone())
+            .build()
+    }
+
+    pub fn stream_options(&self) -> FindOptions {
+        FindOptions::builder()
+            .limit(self.limit)
+            .skip(self.skip)
+            .sort(self.sort_document())
+            .projection(self.projection.clone())
+            .build()
+    }
+
+    /// Restricts `query` to the documents that sort after the cursor.
+    ///
+    /// `$gt`/`$lt` only match values of the cursor value's type, so the
+    /// types MongoDB sorts after it (before it when descending) are matched
+    /// with `$type`. Null and missing fields sort together before every
+    /// other type, so a null cursor value is followed by any set value when
+    /// ascending and by nothing when descending, while a set value is
+    /// followed by the nulls when descending.
+    pub fn filter(&self, query: Document) -> Document {
+        let Some(values) = &self.after else {
+            return query;
+        };
+        let mut alternatives: Vec<Document> = Vec::new();
+        for (position, (field, direction)) in self.sort.iter().enumerate() {
+            let mut equal: Document = doc! {};
+            for ((previous, _), value) in self.sort[..position].iter().zip(values) {
+                equal.insert(previous, doc! { "$eq": value.clone() });
+            }
+            let value: &Bson = &values[position];
+            let after: Vec<Document> = match (value, *direction == 1) {
+                (Bson::Null, true) => vec![doc! { "$ne": Bson::Null }],
+                (Bson::Null, false) => Vec::new(),
+                (value, true) => {
+                    let mut after: Vec<Document> = vec![doc! { "$gt": value.clone() }];
+                    if let Some(rank) = type_rank(value) {
+                        after.extend(types(&TYPE_ORDER[rank + 1..]));
+                    }
+                    after
+                }
+                (value, false) => {
+                    let mut after: Vec<Document> = vec![doc! { "$lt": value.clone() }];
+                    if let Some(rank) = type_rank(value) {
+                        after.extend(types(&TYPE_ORDER[..rank]));
+                    }
+                    after.push(doc! { "$eq": Bson::Null });
+                    after
+                }
+            };
+            for condition in after {
+                let mut branch: Document = equal.clone();
+                branch.insert(field, condition);
+                alternatives.push(branch);
+            }
+        }
+        if query.is_empty() {
+            doc! { "$or": alternatives }
+        } else {
+            doc! { "$and": [query, { "$or": alternatives }] }
+        }
+    }
+
+    /// Drops the extra document fetched by `options` and returns the cursor
+    /// of the next page, if there is one.
+    pub fn finish(
+        &self,
+        mut documents: Vec<Document>,
+    ) -> Result<(Vec<Document>, Option<String>), ApiError> {
+        let mut next_cursor: Option<String> = None;
+        if documents.len() as i64 > self.page_size() {
+            documents.truncate(self.page_size() as usize);
+            next_cursor = documents
+                .last()
+                .map(|last| self.encode_cursor(last))
+                .transpose()?;
+        }
+        for document in documents.iter_mut() {
+            self.strip(document);
+        }
+        Ok((documents, next_cursor))
+    }
+
+    /// Removes the sort fields that were only fetched for the cursor.
+    pub fn strip(&self, document: &mut Document) {
+        for field in self.hidden.iter() {
+            remove_path(document, field);
+        }
+    }
+
+    /// Missing sort fields are recorded as null, which is where MongoDB
+    /// sorts them.
+    fn encode_cursor(&self, last: &Document) -> Result<String, ApiError> {
+        let mut values: Vec<Bson> = Vec::new();
+        for (field, _) in self.sort.iter() {
+            match get_path(last, field) {
+                Some(Bson::Array(_)) => {
+                    return Err(ApiError::BadRequest(format!(
+                        "Cannot page by `{field}`, it holds an array"
+                    )))
+                }
+                Some(value) => values.push(value.clone()),
+                None => values.push(Bson::Null),
+            }
+        }
+        let token: Document = doc! { "sort": self.sort_document(), "values": values };
+        let mut bytes: Vec<u8> = Vec::new();
+        // Writing a document to a Vec cannot fail.
+        token.to_writer(&mut bytes).unwrap_or_default();
+        Ok(URL_SAFE_NO_PAD.encode(bytes))
+    }
+
+    fn decode_cursor(&self, token: &str) -> Result<Vec<Bson>, ApiError> {
+        let invalid = || ApiError::BadRequest(String::from("The `after` cursor is invalid"));
+        let bytes: Vec<u8> = URL_SAFE_NO_PAD.decode(token).map_err(|_| invalid())?;
+        let token: Document = Document::from_reader(bytes.as_slice()).map_err(|_| invalid())?;
+        if token.get_document("sort").ok() != Some(&self.sort_document()) {
+            return Err(ApiError::BadRequest(String::from(
+                "The `after` cursor was issued for a different sort",
+            )));
+        }
+        match token.get_array("values") {
+            Ok(values) if values.len() == self.sort.len() => Ok(values.clone()),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Position of the value's type in `TYPE_ORDER`.
+fn type_rank(value: &Bson) -> Option<usize> {
+    let alias: &str = match value {
+        Bson::Int32(_) | Bson::Int64(_) | Bson::Double(_) | Bson::Decimal128(_) => "number",
+        Bson::String(_) | Bson::Symbol(_) => "string",
+        Bson::Document(_) => "object",
+        Bson::Binary(_) => "binData",
+        Bson::ObjectId(_) => "objectId",
+        Bson::Boolean(_) => "bool",
+        Bson::DateTime(_) => "date",
+        Bson::Timestamp(_) => "timestamp",
+        Bson::RegularExpression(_) => "regex",
+        _ => return None,
+    };
+    TYPE_ORDER
+        .iter()
+        .position(|aliases| aliases.contains(&alias))
+}
+
+/// Matches the values of any of the given type groups.
+fn types(groups: &[&[&str]]) -> Option<Document> {
+    let aliases: Vec<&str> = groups.concat();
+    match aliases.is_empty() {
+        true => None,
+        false => Some(doc! { "$type": aliases }),
+    }
+}
+
+fn list(value: Option<&String>) -> Vec<&str> {
+    value
+        .map(|value| {
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|field| !field.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Builds the projection for `fields=name,age` (only these) or
+/// `fields=-password` (all but these). Sort fields the projection would
+/// leave out are still fetched and listed as hidden.
+fn projection(fields: Vec<&str>, sort: &[(String, i32)]) -> (Option<Document>, Vec<String>) {
+    if fields.is_empty() {
+        return (None, Vec::new());
+    }
+    let mut projection: Document = doc! {};
+    for field in fields.iter() {
+        match field.strip_prefix('-') {
+            Some(field) => projection.insert(field, 0),
+            None => projection.insert(field.strip_prefix('+').unwrap_or(field), 1),
+        };
+    }
+    let including: bool = projection
+        .iter()
+        .any(|(field, value)| field != "_id" && value == &Bson::Int32(1));
+    let mut hidden: Vec<String> = Vec::new();
+    for (field, _) in sort.iter() {
+        match projection.get(field) {
+            Some(Bson::Int32(0)) => {
+                projection.remove(field);
+                hidden.push(field.clone());
+            }
+            None if including && field != "_id" => {
+                projection.insert(field, 1);
+                hidden.push(field.clone());
+            }
+            _ => {}
+        }
+    }
+    (Some(projection), hidden)
+}
+
+fn get_path<'a>(document: &'a Document, path: &str) -> Option<&'a Bson> {
+    match path.split_once('.') {
+        Some((head, rest)) => get_path(document.get_document(head).ok()?, rest),
+        None => document.get(path),
+    }
+}
+
+fn remove_path(document: &mut Document, path: &str) {
+    match path.split_once('.') {
+        Some((head, rest)) => {
+            if let Ok(inner) = document.get_document_mut(head) {
+                remove_path(inner, rest);
+                if inner.is_empty() {
+                    document.remove(head);
+                }
+            }
+        }
+        None => {
+            document.remove(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::query_string;
+
+    fn page(pairs: &[(&str, &str)]) -> Result<Page, ApiError> {
+        Page::from_query(&mut query_string(pairs))
+    }
+
+    fn after(sort: &str, last: Document) -> Page {
+        let cursor: String = page(&[("sort", sort)])
+            .unwrap()
+            .encode_cursor(&last)
+            .unwrap();
+        page(&[("sort", sort), ("after", &cursor)]).unwrap()
+    }
+
+    #[test]
+    fn paging_parameters_are_taken_out() {
+        let mut searches: HashMap<String, String> = HashMap::from([
+            (String::from("limit"), String::from("10")),
+            (String::from("name"), String::from("bob")),
+        ]);
+        let page: Page = Page::from_query(&mut searches).unwrap();
+        assert_eq!(page.limit, Some(10));
+        assert_eq!(searches.len(), 1);
+        assert_eq!(page.sort, vec![(String::from("_id"), 1)]);
+    }
+
+    #[test]
+    fn invalid_parameters_are_rejected() {
+        assert!(page(&[("limit", "0")]).is_err());
+        assert!(page(&[("limit", "1001")]).is_err());
+        assert!(page(&[("skip", "-1")]).is_err());
+        assert!(page(&[("sort", "age,-age")]).is_err());
+        assert!(page(&[("after", "not a cursor")]).is_err());
+    }
+
+    #[test]
+    fn cursors_round_trip() {
+        let page: Page = after("-age", doc! { "_id": 7, "age": 30 });
+        assert_eq!(page.after, Some(vec![Bson::Int32(30), Bson::Int32(7)]));
+    }
+
+    #[test]
+    fn cursors_are_tied_to_their_sort() {
+        let cursor: String = page(&[("sort", "age")])
+            .unwrap()
+            .encode_cursor(&doc! { "_id": 1, "age": 3 })
+            .unwrap();
+        assert!(page(&[("sort", "-age"), ("after", &cursor)]).is_err());
+    }
+
+    /// Every type MongoDB sorts after numbers.
+    fn after_numbers() -> Document {
+        doc! { "$type": [
+            "string", "symbol", "object", "binData", "objectId", "bool", "date", "timestamp",
+            "regex",
+        ] }
+    }
+
+    #[test]
+    fn filter_continues_after_the_cursor() {
+        let page: Page = after("age", doc! { "_id": 7, "age": 30 });
+        assert_eq!(
+            page.filter(doc! { "name": "bob" }),
+            doc! { "$and": [
+                { "name": "bob" },
+                { "$or": [
+                    { "age": { "$gt": 30 } },
+                    { "age": after_numbers() },
+                    { "age": { "$eq": 30 }, "_id": { "$gt": 7 } },
+                    { "age": { "$eq": 30 }, "_id": after_numbers() },
+                ] },
+            ] }
+        );
+    }
+
+    #[test]
+    fn missing_values_sort_as_null() {
+        let page: Page = after("age", doc! { "_id": 7 });
+        assert_eq!(
+            page.filter(doc! {}),
+            doc! { "$or": [
+                { "age": { "$ne": null } },
+                { "age": { "$eq": null }, "_id": { "$gt": 7 } },
+                { "age": { "$eq": null }, "_id": after_numbers() },
+            ] }
+        );
+        let page: Page = after("-age", doc! { "_id": 7, "age": Bson::Null });
+        assert_eq!(
+            page.filter(doc! {}),
+            doc! { "$or": [
+                { "age": { "$eq": null }, "_id": { "$gt": 7 } },
+                { "age": { "$eq": null }, "_id": after_numbers() },
+            ] }
+        );
+    }
+
+    #[test]
+    fn descending_values_are_followed_by_nulls() {
+        let page: Page = after("-age", doc! { "_id": 7, "age": 30 });
+        assert_eq!(
+            page.filter(doc! {}),
+            doc! { "$or": [
+                { "age": { "$lt": 30 } },
+                { "age": { "$eq": null } },
+                { "age": { "$eq": 30 }, "_id": { "$gt": 7 } },
+                { "age": { "$eq": 30 }, "_id": after_numbers() },
+            ] }
+        );
+    }
+
+    #[test]
+    fn other_types_follow_in_mongo_sort_order() {
+        let tags: Page = page(&[("sort", "tags")]).unwrap();
+        assert!(tags
+            .encode_cursor(&doc! { "_id": 1, "tags": [1, 2] })
+            .is_err());
+        let page: Page = after("_id", doc! { "_id": "m" });
+        assert_eq!(
+            page.filter(doc! {}),
+            doc! { "$or": [
+                { "_id": { "$gt": "m" } },
+                { "_id": { "$type": [
+                    "object", "binData", "objectId", "bool", "date", "timestamp", "regex",
+                ] } },
+            ] }
+        );
+        let page: Page = after("-_id", doc! { "_id": "m" });
+        assert_eq!(
+            page.filter(doc! {}),
+            doc! { "$or": [
+                { "_id": { "$lt": "m" } },
+                { "_id": { "$type": ["number"] } },
+                { "_id": { "$eq": null } },
+            ] }
+        );
+        // An int cursor is followed by larger doubles through `$gt`.
+        let page: Page = after("_id", doc! { "_id": 1 });
+        assert_eq!(
+            page.filter(doc! {}),
+            doc! { "$or": [{ "_id": { "$gt": 1 } }, { "_id": after_numbers() }] }
+        );
+    }
+
+    #[test]
+    fn hidden_sort_fields_are_fetched_then_stripped() {
+        let page: Page = page(&[("sort", "meta.rank"), ("fields", "name")]).unwrap();
+        assert_eq!(page.hidden, vec![String::from("meta.rank")]);
+        let mut document: Document = doc! { "_id": 1, "name": "a", "meta": { "rank": 2 } };
+        page.strip(&mut document);
+        assert_eq!(document, doc! { "_id": 1, "name": "a" });
+    }
+}
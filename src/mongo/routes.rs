@@ -1,19 +1,27 @@
 use super::mongoose::{
     delete_many, delete_one, drop_collection, drop_database, find_many, find_one,
     get_all_databases, increment, index, insert_many, insert_one, pop_first, pop_last,
-    push_element, rename_field, show_collections_in_a_database, update_many, update_one,
+    push_element, query, rename_field, show_collections_in_a_database, update_many, update_one,
 };
+use crate::middleware::auth::RequireApiKey;
 use actix_web::web;
 
-pub fn mongo_config(cfg: &mut web::ServiceConfig) {
+pub fn mongo_config(
+    cfg: &mut web::ServiceConfig,
+    prefix: &str,
+    client: web::Data<mongodb::Client>,
+) {
     cfg.service(
-        web::scope("/mongodb")
+        web::scope(prefix)
+            .app_data(client)
+            .wrap(RequireApiKey)
             .route("", web::get().to(index))
             .route("/find_one/{database}/{collection}", web::get().to(find_one))
             .route(
                 "/find_many/{database}/{collection}",
                 web::get().to(find_many),
             )
+            .route("/query/{database}/{collection}", web::post().to(query))
             .route(
                 "/insert_one/{database}/{collection}",
                 web::post().to(insert_one),
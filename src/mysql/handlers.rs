@@ -0,0 +1,293 @@
+use actix_web::{web, HttpResponse, Responder};
+use mysql_async::{
+    consts::ColumnType, prelude::Queryable, Conn, Pool, Row, TxOpts, Value as MySqlValue,
+};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+
+use crate::{
+    body::{body_object, body_rows},
+    error::ApiError,
+};
+
+pub async fn index() -> impl Responder {
+    "Hello from MySQL!"
+}
+
+fn quote_identifier(identifier: &str) -> String {
+    format!("`{}`", identifier.replace('`', "``"))
+}
+
+fn where_clause(searches: &HashMap<String, String>) -> (String, Vec<MySqlValue>) {
+    if searches.is_empty() {
+        return (String::new(), Vec::new());
+    }
+    let mut conditions: Vec<String> = Vec::new();
+    let mut values: Vec<MySqlValue> = Vec::new();
+    for (key, value) in searches.iter() {
+        conditions.push(format!("{} = ?", quote_identifier(key)));
+        values.push(MySqlValue::from(value.as_str()));
+    }
+    (format!(" WHERE {}", conditions.join(" AND ")), values)
+}
+
+fn json_to_mysql(value: &Value) -> MySqlValue {
+    match value {
+        Value::Null => MySqlValue::NULL,
+        Value::Bool(b) => MySqlValue::Int(*b as i64),
+        Value::Number(n) => {
+            if let Some(i) = n.as_i64() {
+                MySqlValue::Int(i)
+            } else if let Some(u) = n.as_u64() {
+                MySqlValue::UInt(u)
+            } else {
+                MySqlValue::Double(n.as_f64().unwrap_or_default())
+            }
+        }
+        Value::String(s) => MySqlValue::from(s.as_str()),
+        Value::Array(_) | Value::Object(_) => MySqlValue::from(value.to_string()),
+    }
+}
+
+fn mysql_to_json(value: MySqlValue, column_type: ColumnType) -> Value {
+    match value {
+        MySqlValue::NULL => Value::Null,
+        MySqlValue::Bytes(bytes) => {
+            if column_type == ColumnType::MYSQL_TYPE_JSON {
+                if let Ok(value) = serde_json::from_slice(&bytes) {
+                    return value;
+                }
+            }
+            Value::String(String::from_utf8_lossy(&bytes).into_owned())
+        }
+        MySqlValue::Int(i) => json!(i),
+        MySqlValue::UInt(u) => json!(u),
+        MySqlValue::Float(f) => json!(f),
+        MySqlValue::Double(d) => json!(d),
+        MySqlValue::Date(year, month, day, hour, minute, second, micros) => {
+            if column_type == ColumnType::MYSQL_TYPE_DATE {
+                Value::String(format!("{year:04}-{month:02}-{day:02}"))
+            } else if micros > 0 {
+                Value::String(format!(
+                    "{year:04}-{month:02}-{day:02} {hour:02}:{minute:02}:{second:02}.{micros:06}"
+                ))
+            } else {
+                Value::String(format!(
+                    "{year:04}-{month:02}-{day:02} {hour:02}:{minute:02}:{second:02}"
+                ))
+            }
+        }
+        MySqlValue::Time(negative, days, hours, minutes, seconds, micros) => {
+            let sign: &str = if negative { "-" } else { "" };
+            let hours: u32 = days * 24 + hours as u32;
+            if micros > 0 {
+                Value::String(format!(
+                    "{sign}{hours:02}:{minutes:02}:{seconds:02}.{micros:06}"
+                ))
+            } else {
+                Value::String(format!("{sign}{hours:02}:{minutes:02}:{seconds:02}"))
+            }
+        }
+    }
+}
+
+fn row_to_json(row: Row) -> Value {
+    let columns: Vec<(String, ColumnType)> = row
+        .columns_ref()
+        .iter()
+        .map(|column| (column.name_str().into_owned(), column.column_type()))
+        .collect();
+    let mut object: Map<String, Value> = Map::new();
+    for ((name, column_type), value) in columns.into_iter().zip(row.unwrap()) {
+        object.insert(name, mysql_to_json(value, column_type));
+    }
+    Value::Object(object)
+}
+
+async fn select(
+    pool: &Pool,
+    query: String,
+    values: Vec<MySqlValue>,
+) -> Result<Vec<Row>, mysql_async::Error> {
+    let mut conn: Conn = pool.get_conn().await?;
+    conn.exec(query, values).await
+}
+
+async fn execute(
+    pool: &Pool,
+    query: String,
+    values: Vec<MySqlValue>,
+) -> Result<u64, mysql_async::Error> {
+    let mut conn: Conn = pool.get_conn().await?;
+    conn.exec_drop(query, values).await?;
+    Ok(conn.affected_rows())
+}
+
+pub async fn find_one(
+    params: web::Path<String>,
+    searches: web::Query<HashMap<String, String>>,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, ApiError> {
+    let (filter, values) = where_clause(&searches);
+    let query: String = format!(
+        "SELECT * FROM {}{filter} LIMIT 1",
+        quote_identifier(&params)
+    );
+    match select(&pool, query, values).await?.into_iter().next() {
+        Some(row) => Ok(HttpResponse::Ok().json(row_to_json(row))),
+        None => Err(ApiError::NotFound(String::from("No matching row found"))),
+    }
+}
+
+pub async fn find_many(
+    params: web::Path<String>,
+    searches: web::Query<HashMap<String, String>>,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, ApiError> {
+    let (filter, values) = where_clause(&searches);
+    let query: String = format!("SELECT * FROM {}{filter}", quote_identifier(&params));
+    let rows: Vec<Row> = select(&pool, query, values).await?;
+    let response: Vec<Value> = rows.into_iter().map(row_to_json).collect();
+    Ok(HttpResponse::Ok().json(response))
+}
+
+fn insert_statement(table: &str, row: &Map<String, Value>) -> (String, Vec<MySqlValue>) {
+    let columns: Vec<String> = row.keys().map(|key| quote_identifier(key)).collect();
+    let placeholders: Vec<&str> = vec!["?"; row.len()];
+    let query: String = format!(
+        "INSERT INTO {} ({}) VALUES ({})",
+        quote_identifier(table),
+        columns.join(", "),
+        placeholders.join(", ")
+    );
+    (query, row.values().map(json_to_mysql).collect())
+}
+
+pub async fn insert_one(
+    params: web::Path<String>,
+    body: web::Json<Value>,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, ApiError> {
+    let row: &Map<String, Value> = body_object(&body, "value")?;
+    let (query, values) = insert_statement(&params, row);
+    let mut conn: Conn = pool.get_conn().await?;
+    conn.exec_drop(query, values).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "inserted_id": conn.last_insert_id(),
+        "affected_rows": conn.affected_rows()
+    })))
+}
+
+pub async fn insert_many(
+    params: web::Path<String>,
+    body: web::Json<Value>,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, ApiError> {
+    let rows: Vec<&Map<String, Value>> = body_rows(&body)?;
+    let mut conn: Conn = pool.get_conn().await?;
+    // Dropping the transaction when a row fails rolls the previous ones back.
+    let mut transaction = conn.start_transaction(TxOpts::default()).await?;
+    let mut inserted_ids: Vec<Option<u64>> = Vec::new();
+    for row in rows {
+        let (query, values) = insert_statement(&params, row);
+        transaction.exec_drop(query, values).await?;
+        inserted_ids.push(transaction.last_insert_id());
+    }
+    transaction.commit().await?;
+    Ok(HttpResponse::Ok().json(json!({ "inserted_ids": inserted_ids })))
+}
+
+async fn update(
+    table: &str,
+    searches: &HashMap<String, String>,
+    body: &Value,
+    pool: &Pool,
+    limit: &str,
+) -> Result<HttpResponse, ApiError> {
+    let changes: &Map<String, Value> = body_object(body, "value")?;
+    let assignments: Vec<String> = changes
+        .keys()
+        .map(|key| format!("{} = ?", quote_identifier(key)))
+        .collect();
+    let mut values: Vec<MySqlValue> = changes.values().map(json_to_mysql).collect();
+    let (filter, filter_values) = where_clause(searches);
+    values.extend(filter_values);
+    let query: String = format!(
+        "UPDATE {} SET {}{filter}{limit}",
+        quote_identifier(table),
+        assignments.join(", ")
+    );
+    let affected_rows: u64 = execute(pool, query, values).await?;
+    Ok(HttpResponse::Ok().json(json!({ "modified_count": affected_rows })))
+}
+
+pub async fn update_one(
+    params: web::Path<String>,
+    searches: web::Query<HashMap<String, String>>,
+    body: web::Json<Value>,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, ApiError> {
+    update(&params, &searches, &body, &pool, " LIMIT 1").await
+}
+
+pub async fn update_many(
+    params: web::Path<String>,
+    searches: web::Query<HashMap<String, String>>,
+    body: web::Json<Value>,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, ApiError> {
+    update(&params, &searches, &body, &pool, "").await
+}
+
+async fn delete(
+    table: &str,
+    searches: &HashMap<String, String>,
+    pool: &Pool,
+    limit: &str,
+) -> Result<HttpResponse, ApiError> {
+    let (filter, values) = where_clause(searches);
+    let query: String = format!("DELETE FROM {}{filter}{limit}", quote_identifier(table));
+    let deleted_count: u64 = execute(pool, query, values).await?;
+    Ok(HttpResponse::Ok().json(json!({ "deleted_count": deleted_count })))
+}
+
+pub async fn delete_one(
+    params: web::Path<String>,
+    searches: web::Query<HashMap<String, String>>,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, ApiError> {
+    delete(&params, &searches, &pool, " LIMIT 1").await
+}
+
+pub async fn delete_many(
+    params: web::Path<String>,
+    searches: web::Query<HashMap<String, String>>,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, ApiError> {
+    delete(&params, &searches, &pool, "").await
+}
+
+pub async fn get_tables(pool: web::Data<Pool>) -> Result<HttpResponse, ApiError> {
+    let mut conn: Conn = pool.get_conn().await?;
+    let tables: Vec<String> = conn.query("SHOW TABLES").await?;
+    Ok(HttpResponse::Ok().json(tables))
+}
+
+pub async fn describe_table(
+    params: web::Path<String>,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, ApiError> {
+    let query: String = String::from(
+        "SELECT COLUMN_NAME AS name, COLUMN_TYPE AS type, IS_NULLABLE AS nullable, \
+         COLUMN_KEY AS `key`, COLUMN_DEFAULT AS `default`, EXTRA AS extra \
+         FROM information_schema.COLUMNS \
+         WHERE TABLE_SCHEMA = DATABASE() AND TABLE_NAME = ? \
+         ORDER BY ORDINAL_POSITION",
+    );
+    let rows: Vec<Row> = select(&pool, query, vec![MySqlValue::from(params.as_str())]).await?;
+    if rows.is_empty() {
+        return Err(ApiError::NotFound(format!("Table {} not found", params)));
+    }
+    let columns: Vec<Value> = rows.into_iter().map(row_to_json).collect();
+    Ok(HttpResponse::Ok().json(columns))
+}
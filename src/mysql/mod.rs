@@ -0,0 +1,2 @@
+mod handlers;
+pub mod routes;
@@ -0,0 +1,30 @@
+use actix_web::web;
+
+use crate::middleware::auth::RequireApiKey;
+
+use super::handlers::{
+    delete_many, delete_one, describe_table, find_many, find_one, get_tables, index, insert_many,
+    insert_one, update_many, update_one,
+};
+pub fn mysql_config(
+    cfg: &mut web::ServiceConfig,
+    prefix: &str,
+    client: web::Data<mysql_async::Pool>,
+) {
+    cfg.service(
+        web::scope(prefix)
+            .app_data(client)
+            .wrap(RequireApiKey)
+            .route("", web::get().to(index))
+            .route("/find_one/{table}", web::get().to(find_one))
+            .route("/find_many/{table}", web::get().to(find_many))
+            .route("/insert_one/{table}", web::post().to(insert_one))
+            .route("/insert_many/{table}", web::post().to(insert_many))
+            .route("/update_one/{table}", web::put().to(update_one))
+            .route("/update_many/{table}", web::put().to(update_many))
+            .route("/delete_one/{table}", web::delete().to(delete_one))
+            .route("/delete_many/{table}", web::delete().to(delete_many))
+            .route("/get_tables", web::get().to(get_tables))
+            .route("/describe/{table}", web::get().to(describe_table)),
+    );
+}
@@ -1,2 +1,6 @@
+pub mod pool;
 mod postgres;
+mod query;
 pub mod routes;
+pub mod tls;
+mod types;
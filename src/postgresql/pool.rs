@@ -0,0 +1,51 @@
+use super::tls;
+use crate::{
+    commands::init::{ConnectionConfig, PoolConfig},
+    error::postgres_message,
+};
+use deadpool_postgres::{Manager, ManagerConfig, Pool, PoolError, RecyclingMethod, Runtime};
+use std::{io, time::Duration};
+
+const DEFAULT_MAX_SIZE: usize = 16;
+const DEFAULT_WAIT_TIMEOUT: u64 = 30;
+const DEFAULT_CONNECT_TIMEOUT: u64 = 10;
+const DEFAULT_RECYCLE_TIMEOUT: u64 = 5;
+
+/// Builds the connection pool and checks out one connection so a wrong
+/// uri or password stops the server at startup instead of on the first
+/// request. Connections that died are dropped and replaced on checkout.
+pub async fn connect(uri: &str, connection: &ConnectionConfig) -> io::Result<Pool> {
+    let (connection_config, connector) =
+        tls::connect_config(uri, connection.database_tls.as_ref())?;
+    let settings: PoolConfig = connection.pool.clone().unwrap_or_default();
+    let recycling_method: RecyclingMethod = match settings.health_check.unwrap_or(true) {
+        true => RecyclingMethod::Verified,
+        false => RecyclingMethod::Fast,
+    };
+    let manager: Manager = Manager::from_config(
+        connection_config,
+        connector,
+        ManagerConfig { recycling_method },
+    );
+    let seconds =
+        |value: Option<u64>, default: u64| Some(Duration::from_secs(value.unwrap_or(default)));
+    let pool: Pool = Pool::builder(manager)
+        .max_size(settings.max_size.unwrap_or(DEFAULT_MAX_SIZE))
+        .wait_timeout(seconds(settings.wait_timeout, DEFAULT_WAIT_TIMEOUT))
+        .create_timeout(seconds(settings.connect_timeout, DEFAULT_CONNECT_TIMEOUT))
+        .recycle_timeout(seconds(settings.recycle_timeout, DEFAULT_RECYCLE_TIMEOUT))
+        .runtime(Runtime::Tokio1)
+        .build()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+    let _connection = pool.get().await.map_err(|e| {
+        let details: String = match &e {
+            PoolError::Backend(e) => postgres_message(e),
+            e => e.to_string(),
+        };
+        io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!("Failed to connect to Postgres: {details}"),
+        )
+    })?;
+    Ok(pool)
+}
@@ -1,8 +1,22 @@
 use std::collections::HashMap;
 
-use actix_web::{web, Responder};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use deadpool_postgres::Object;
+use futures::TryStreamExt;
+use serde_json::{json, Map, Value};
+use tokio_postgres::{Row, RowStream};
 
-use crate::commands::init::AppState;
+use super::{
+    query::{param_refs, QueryBuilder, SqlParam, Statement, Table},
+    types::{row_to_json, rows_to_json},
+};
+use crate::{
+    body::{body_object, body_rows},
+    commands::init::AppState,
+    error::ApiError,
+    logging::timed,
+    stream::{ndjson, wants_ndjson},
+};
 
 pub async fn index() -> impl Responder {
     "Hello from Postgres!"
@@ -12,34 +26,230 @@ pub async fn find_one(
     params: web::Path<String>,
     searches: web::Query<HashMap<String, String>>,
     client: web::Data<AppState>,
-) -> impl Responder {
-    let mut query: String = String::from("SELECT * FROM ");
-    query.push_str(&params);
-    query.push_str(" WHERE ");
-    let mut count: usize = 0;
-    for (key, value) in searches.iter() {
-        if count > 0 {
-            query.push_str(" AND ");
-        }
-        query.push_str(key);
-        query.push_str(" = '");
-        query.push_str(value);
-        query.push_str("'");
-        count += 1;
+) -> Result<HttpResponse, ApiError> {
+    let client: Object = client.db.get().await?;
+    let table: Table = Table::resolve(&client, &params).await?;
+    let statement: Statement = QueryBuilder::new(&table).filter(&searches)?.select(Some(1));
+    match timed(
+        &statement.sql,
+        client.query_opt(&statement.sql, &statement.params()),
+    )
+    .await?
+    {
+        Some(row) => Ok(HttpResponse::Ok().json(row_to_json(&row))),
+        None => Err(ApiError::NotFound(String::from("No matching row found"))),
     }
-    let database: &std::sync::Arc<futures::lock::Mutex<tokio_postgres::Client>> = &client.db;
-    let client: futures::lock::MutexGuard<'_, tokio_postgres::Client> = database.lock().await;
-    let rows: Vec<tokio_postgres::Row> = client.query(&query, &[]).await.unwrap();
-    println!("{:?}", rows);
-    println!("{query}");
-    // let mut response: Vec<HashMap<String, String>> = Vec::new();
-    // for row in rows {
-    //     let mut map: HashMap<String, String> = HashMap::new();
-    //     for (i, column) in row.columns().iter().enumerate() {
-    //         map.insert(column.name().to_string(), row.get(i));
-    //     }
-    //     response.push(map);
-    // }
-    // web::Json(response)
-    "Hello from Postgres!"
+}
+
+pub async fn find_many(
+    req: HttpRequest,
+    params: web::Path<String>,
+    searches: web::Query<HashMap<String, String>>,
+    client: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let client: Object = client.db.get().await?;
+    let table: Table = Table::resolve(&client, &params).await?;
+    let statement: Statement = QueryBuilder::new(&table).filter(&searches)?.select(None);
+    if wants_ndjson(&req) {
+        let rows: RowStream = timed(
+            &statement.sql,
+            client.query_raw(&statement.sql, statement.params()),
+        )
+        .await?;
+        // The rows are read from the pooled connection while they are sent,
+        // so the stream keeps it checked out until the last row or until the
+        // client goes away.
+        return Ok(ndjson(rows.map_ok(move |row| {
+            let _checked_out: &Object = &client;
+            row_to_json(&row)
+        })));
+    }
+    let rows: Vec<Row> = timed(
+        &statement.sql,
+        client.query(&statement.sql, &statement.params()),
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(rows_to_json(&rows)))
+}
+
+pub async fn insert_one(
+    params: web::Path<String>,
+    body: web::Json<Value>,
+    client: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let row: &Map<String, Value> = body_object(&body, "value")?;
+    let client: Object = client.db.get().await?;
+    let table: Table = Table::resolve(&client, &params).await?;
+    let statement: Statement = QueryBuilder::new(&table).insert(row)?;
+    let row: Row = timed(
+        &statement.sql,
+        client.query_one(&statement.sql, &statement.params()),
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(row_to_json(&row)))
+}
+
+pub async fn insert_many(
+    params: web::Path<String>,
+    body: web::Json<Value>,
+    client: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let rows: Vec<&Map<String, Value>> = body_rows(&body)?;
+    let mut client: Object = client.db.get().await?;
+    let table: Table = Table::resolve(&client, &params).await?;
+    let statements: Vec<Statement> = rows
+        .into_iter()
+        .map(|row| QueryBuilder::new(&table).insert(row))
+        .collect::<Result<_, _>>()?;
+    // One transaction, so that a failing row leaves none of them inserted.
+    let transaction = client.transaction().await?;
+    let mut inserted: Vec<Value> = Vec::new();
+    for statement in statements {
+        let row: Row = timed(
+            &statement.sql,
+            transaction.query_one(&statement.sql, &statement.params()),
+        )
+        .await?;
+        inserted.push(row_to_json(&row));
+    }
+    transaction.commit().await?;
+    Ok(HttpResponse::Ok().json(inserted))
+}
+
+async fn update(
+    table: &str,
+    searches: &HashMap<String, String>,
+    body: &Value,
+    client: &AppState,
+    single_row: bool,
+) -> Result<HttpResponse, ApiError> {
+    let changes: &Map<String, Value> = body_object(body, "value")?;
+    let client: Object = client.db.get().await?;
+    let table: Table = Table::resolve(&client, table).await?;
+    let statement: Statement = QueryBuilder::new(&table)
+        .filter(searches)?
+        .update(changes, single_row)?;
+    let rows: Vec<Row> = timed(
+        &statement.sql,
+        client.query(&statement.sql, &statement.params()),
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "modified_count": rows.len(),
+        "rows": rows_to_json(&rows)
+    })))
+}
+
+pub async fn update_one(
+    params: web::Path<String>,
+    searches: web::Query<HashMap<String, String>>,
+    body: web::Json<Value>,
+    client: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    update(&params, &searches, &body, &client, true).await
+}
+
+pub async fn update_many(
+    params: web::Path<String>,
+    searches: web::Query<HashMap<String, String>>,
+    body: web::Json<Value>,
+    client: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    update(&params, &searches, &body, &client, false).await
+}
+
+async fn delete(
+    table: &str,
+    searches: &HashMap<String, String>,
+    client: &AppState,
+    single_row: bool,
+) -> Result<HttpResponse, ApiError> {
+    let client: Object = client.db.get().await?;
+    let table: Table = Table::resolve(&client, table).await?;
+    let statement: Statement = QueryBuilder::new(&table)
+        .filter(searches)?
+        .delete(single_row);
+    let rows: Vec<Row> = timed(
+        &statement.sql,
+        client.query(&statement.sql, &statement.params()),
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "deleted_count": rows.len(),
+        "rows": rows_to_json(&rows)
+    })))
+}
+
+pub async fn delete_one(
+    params: web::Path<String>,
+    searches: web::Query<HashMap<String, String>>,
+    client: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    delete(&params, &searches, &client, true).await
+}
+
+pub async fn delete_many(
+    params: web::Path<String>,
+    searches: web::Query<HashMap<String, String>>,
+    client: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    delete(&params, &searches, &client, false).await
+}
+
+async fn list_names(
+    client: &AppState,
+    query: &str,
+    params: &[SqlParam],
+) -> Result<HttpResponse, ApiError> {
+    let client: Object = client.db.get().await?;
+    let rows: Vec<Row> = timed(query, client.query(query, &param_refs(params))).await?;
+    let names: Vec<String> = rows.iter().map(|row| row.get(0)).collect();
+    Ok(HttpResponse::Ok().json(names))
+}
+
+pub async fn get_all_databases(client: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    list_names(
+        &client,
+        "SELECT datname::text FROM pg_database WHERE NOT datistemplate ORDER BY datname",
+        &[],
+    )
+    .await
+}
+
+pub async fn get_schemas(client: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    list_names(
+        &client,
+        "SELECT schema_name::text FROM information_schema.schemata \
+         WHERE schema_name NOT LIKE 'pg\\_%' AND schema_name <> 'information_schema' \
+         ORDER BY schema_name",
+        &[],
+    )
+    .await
+}
+
+pub async fn get_tables(
+    params: Option<web::Path<String>>,
+    client: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let schema: String = params
+        .map(|params| params.into_inner())
+        .unwrap_or_else(|| String::from("public"));
+    list_names(
+        &client,
+        "SELECT table_name::text FROM information_schema.tables \
+         WHERE table_schema = $1 AND table_type = 'BASE TABLE' ORDER BY table_name",
+        &[SqlParam::Text(schema)],
+    )
+    .await
+}
+
+pub async fn drop_table(
+    params: web::Path<String>,
+    client: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let client: Object = client.db.get().await?;
+    let table: Table = Table::resolve(&client, &params).await?;
+    let statement: String = format!("DROP TABLE {}", table.qualified_name());
+    timed(&statement, client.batch_execute(&statement)).await?;
+    Ok(HttpResponse::Ok().body(format!("Table {} dropped", params)))
 }
@@ -0,0 +1,412 @@
+use std::{collections::HashMap, error::Error};
+
+use bytes::BytesMut;
+use serde_json::{Map, Value};
+use tokio_postgres::{
+    types::{to_sql_checked, Format, IsNull, Kind, ToSql, Type},
+    Client, Row,
+};
+
+use crate::{error::ApiError, logging::timed};
+
+/// A value bound to a `$n` placeholder. Values are sent in the text format so
+/// Postgres parses them with the input function of whatever type the
+/// placeholder was inferred as, be it `int4`, `uuid`, `timestamptz` or `numeric`.
+#[derive(Debug)]
+pub enum SqlParam {
+    Text(String),
+    Json(Value),
+}
+
+fn array_literal(values: &[Value]) -> String {
+    let elements: Vec<String> = values
+        .iter()
+        .map(|value| match value {
+            Value::Null => String::from("NULL"),
+            Value::Array(values) => array_literal(values),
+            Value::String(s) => format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\"")),
+            Value::Object(_) => {
+                let text: String = value.to_string();
+                format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+            }
+            Value::Bool(_) | Value::Number(_) => value.to_string(),
+        })
+        .collect();
+    format!("{{{}}}", elements.join(","))
+}
+
+impl ToSql for SqlParam {
+    fn to_sql(
+        &self,
+        ty: &Type,
+        out: &mut BytesMut,
+    ) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        let text: String = match self {
+            SqlParam::Text(text) => text.clone(),
+            SqlParam::Json(Value::Null) => return Ok(IsNull::Yes),
+            // A json column takes the serialized value, quotes included.
+            SqlParam::Json(value) if *ty == Type::JSON || *ty == Type::JSONB => value.to_string(),
+            SqlParam::Json(Value::String(s)) => s.clone(),
+            SqlParam::Json(Value::Array(values)) if matches!(ty.kind(), Kind::Array(_)) => {
+                array_literal(values)
+            }
+            SqlParam::Json(value) => value.to_string(),
+        };
+        out.extend_from_slice(text.as_bytes());
+        Ok(IsNull::No)
+    }
+
+    fn accepts(_ty: &Type) -> bool {
+        true
+    }
+
+    fn encode_format(&self, _ty: &Type) -> Format {
+        Format::Text
+    }
+
+    to_sql_checked!();
+}
+
+pub fn param_refs(params: &[SqlParam]) -> Vec<&(dyn ToSql + Sync)> {
+    params
+        .iter()
+        .map(|param| param as &(dyn ToSql + Sync))
+        .collect()
+}
+
+pub fn quote_identifier(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+const COLUMNS_QUERY: &str = "SELECT n.nspname::text, a.attname::text \
+     FROM pg_catalog.pg_class c \
+     JOIN pg_catalog.pg_namespace n ON n.oid = c.relnamespace \
+     JOIN pg_catalog.pg_attribute a ON a.attrelid = c.oid \
+     WHERE c.relname = $1::text \
+     AND n.nspname = COALESCE($2::text, current_schema()) \
+     AND c.relkind IN ('r', 'p', 'v', 'm', 'f') \
+     AND a.attnum > 0 AND NOT a.attisdropped \
+     ORDER BY a.attnum";
+
+/// A table or view looked up in the catalog. Only names returned by Postgres
+/// itself ever end up in generated SQL.
+#[derive(Debug)]
+pub struct Table {
+    pub schema: String,
+    pub name: String,
+    pub columns: Vec<String>,
+}
+
+impl Table {
+    /// Resolves `table` or `schema.table`. Without a schema the table is
+    /// looked up in the current schema of the connection.
+    pub async fn resolve(client: &Client, table: &str) -> Result<Table, ApiError> {
+        let (schema, name) = match table.split_once('.') {
+            Some((schema, name)) => (Some(schema), name),
+            None => (None, table),
+        };
+        let schema_param: SqlParam = match schema {
+            Some(schema) => SqlParam::Text(schema.to_string()),
+            None => SqlParam::Json(Value::Null),
+        };
+        let rows: Vec<Row> = timed(
+            COLUMNS_QUERY,
+            client.query(
+                COLUMNS_QUERY,
+                &param_refs(&[SqlParam::Text(name.to_string()), schema_param]),
+            ),
+        )
+        .await?;
+        let Some(first) = rows.first() else {
+            return Err(ApiError::NotFound(format!("Table {table} not found")));
+        };
+        Ok(Table {
+            schema: first.get(0),
+            name: name.to_string(),
+            columns: rows.iter().map(|row| row.get(1)).collect(),
+        })
+    }
+
+    pub fn qualified_name(&self) -> String {
+        format!(
+            "{}.{}",
+            quote_identifier(&self.schema),
+            quote_identifier(&self.name)
+        )
+    }
+
+    pub fn column(&self, column: &str) -> Result<String, ApiError> {
+        if self.columns.iter().any(|known| known == column) {
+            Ok(quote_identifier(column))
+        } else {
+            Err(ApiError::BadRequest(format!(
+                "Column {column} does not exist in table {}",
+                self.name
+            )))
+        }
+    }
+}
+
+/// SQL text together with the values for its placeholders.
+#[derive(Debug)]
+pub struct Statement {
+    pub sql: String,
+    pub params: Vec<SqlParam>,
+}
+
+impl Statement {
+    pub fn params(&self) -> Vec<&(dyn ToSql + Sync)> {
+        param_refs(&self.params)
+    }
+}
+
+/// Builds single-table statements. Identifiers are checked against the
+/// resolved `Table` and every value goes through a `$n` bind parameter.
+pub struct QueryBuilder<'a> {
+    table: &'a Table,
+    conditions: Vec<String>,
+    params: Vec<SqlParam>,
+}
+
+impl<'a> QueryBuilder<'a> {
+    pub fn new(table: &'a Table) -> Self {
+        QueryBuilder {
+            table,
+            conditions: Vec::new(),
+            params: Vec::new(),
+        }
+    }
+
+    fn bind(&mut self, param: SqlParam) -> String {
+        self.params.push(param);
+        format!("${}", self.params.len())
+    }
+
+    pub fn filter(mut self, searches: &HashMap<String, String>) -> Result<Self, ApiError> {
+        for (key, value) in searches.iter() {
+            let column: String = self.table.column(key)?;
+            let placeholder: String = self.bind(SqlParam::Text(value.clone()));
+            self.conditions.push(format!("{column} = {placeholder}"));
+        }
+        Ok(self)
+    }
+
+    fn where_clause(&self) -> String {
+        if self.conditions.is_empty() {
+            String::new()
+        } else {
+            format!(" WHERE {}", self.conditions.join(" AND "))
+        }
+    }
+
+    // Postgres has no `LIMIT` on UPDATE/DELETE, so single row variants target
+    // the physical row id of the first match instead. A ctid is only unique
+    // within one partition, hence the pair with the table oid.
+    fn target_clause(&self, single_row: bool) -> String {
+        if single_row {
+            format!(
+                " WHERE (tableoid, ctid) = (SELECT tableoid, ctid FROM {}{} LIMIT 1)",
+                self.table.qualified_name(),
+                self.where_clause()
+            )
+        } else {
+            self.where_clause()
+        }
+    }
+
+    pub fn select(self, limit: Option<u64>) -> Statement {
+        let mut sql: String = format!(
+            "SELECT * FROM {}{}",
+            self.table.qualified_name(),
+            self.where_clause()
+        );
+        if let Some(limit) = limit {
+            sql.push_str(&format!(" LIMIT {limit}"));
+        }
+        Statement {
+            sql,
+            params: self.params,
+        }
+    }
+
+    pub fn insert(mut self, row: &Map<String, Value>) -> Result<Statement, ApiError> {
+        let mut columns: Vec<String> = Vec::new();
+        let mut placeholders: Vec<String> = Vec::new();
+        for (column, value) in row.iter() {
+            columns.push(self.table.column(column)?);
+            placeholders.push(self.bind(SqlParam::Json(value.clone())));
+        }
+        let sql: String = format!(
+            "INSERT INTO {} ({}) VALUES ({}) RETURNING *",
+            self.table.qualified_name(),
+            columns.join(", "),
+            placeholders.join(", ")
+        );
+        Ok(Statement {
+            sql,
+            params: self.params,
+        })
+    }
+
+    pub fn update(
+        mut self,
+        changes: &Map<String, Value>,
+        single_row: bool,
+    ) -> Result<Statement, ApiError> {
+        let mut assignments: Vec<String> = Vec::new();
+        for (column, value) in changes.iter() {
+            let column: String = self.table.column(column)?;
+            let placeholder: String = self.bind(SqlParam::Json(value.clone()));
+            assignments.push(format!("{column} = {placeholder}"));
+        }
+        let sql: String = format!(
+            "UPDATE {} SET {}{} RETURNING *",
+            self.table.qualified_name(),
+            assignments.join(", "),
+            self.target_clause(single_row)
+        );
+        Ok(Statement {
+            sql,
+            params: self.params,
+        })
+    }
+
+    pub fn delete(self, single_row: bool) -> Statement {
+        let sql: String = format!(
+            "DELETE FROM {}{} RETURNING *",
+            self.table.qualified_name(),
+            self.target_clause(single_row)
+        );
+        Statement {
+            sql,
+            params: self.params,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{postgresql::types::to_json, test_support::query_string};
+    use serde_json::json;
+
+    fn table() -> Table {
+        Table {
+            schema: String::from("public"),
+            name: String::from("users"),
+            columns: vec![
+                String::from("id"),
+                String::from("name"),
+                String::from("Mixed\"Case"),
+            ],
+        }
+    }
+
+    fn texts(statement: &Statement) -> Vec<String> {
+        statement
+            .params
+            .iter()
+            .map(|param| match param {
+                SqlParam::Text(text) => text.clone(),
+                SqlParam::Json(value) => value.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn identifiers_are_quoted() {
+        assert_eq!(quote_identifier("users"), "\"users\"");
+        assert_eq!(
+            quote_identifier("a\"; DROP TABLE x; --"),
+            "\"a\"\"; DROP TABLE x; --\""
+        );
+        assert_eq!(table().qualified_name(), "\"public\".\"users\"");
+        assert_eq!(table().column("Mixed\"Case").unwrap(), "\"Mixed\"\"Case\"");
+    }
+
+    #[test]
+    fn unknown_columns_are_rejected() {
+        let table: Table = table();
+        assert!(table.column("password").is_err());
+        assert!(table.column("name; DROP TABLE users").is_err());
+        let injected: HashMap<String, String> = query_string(&[("1=1 OR name", "x")]);
+        assert!(QueryBuilder::new(&table).filter(&injected).is_err());
+        let row: Map<String, Value> = json!({ "id) VALUES (1); --": 1 })
+            .as_object()
+            .cloned()
+            .unwrap();
+        assert!(QueryBuilder::new(&table).insert(&row).is_err());
+    }
+
+    #[test]
+    fn values_are_bound() {
+        let table: Table = table();
+        let statement: Statement = QueryBuilder::new(&table)
+            .filter(&query_string(&[("name", "x' OR '1'='1")]))
+            .unwrap()
+            .select(Some(10));
+        assert_eq!(
+            statement.sql,
+            "SELECT * FROM \"public\".\"users\" WHERE \"name\" = $1 LIMIT 10"
+        );
+        assert_eq!(texts(&statement), vec!["x' OR '1'='1"]);
+    }
+
+    #[test]
+    fn inserts_bind_every_value() {
+        let table: Table = table();
+        let row: Map<String, Value> = json!({ "id": 1, "name": "'); DROP TABLE users; --" })
+            .as_object()
+            .cloned()
+            .unwrap();
+        let statement: Statement = QueryBuilder::new(&table).insert(&row).unwrap();
+        assert_eq!(
+            statement.sql,
+            "INSERT INTO \"public\".\"users\" (\"id\", \"name\") VALUES ($1, $2) RETURNING *"
+        );
+        assert_eq!(texts(&statement), vec!["1", "\"'); DROP TABLE users; --\""]);
+    }
+
+    #[test]
+    fn single_row_changes_target_one_row() {
+        let table: Table = table();
+        let changes: Map<String, Value> = json!({ "name": "b" }).as_object().cloned().unwrap();
+        let statement: Statement = QueryBuilder::new(&table)
+            .filter(&query_string(&[("id", "1")]))
+            .unwrap()
+            .update(&changes, true)
+            .unwrap();
+        assert_eq!(
+            statement.sql,
+            "UPDATE \"public\".\"users\" SET \"name\" = $2 WHERE (tableoid, ctid) = \
+             (SELECT tableoid, ctid FROM \"public\".\"users\" WHERE \"id\" = $1 LIMIT 1) RETURNING *"
+        );
+        let statement: Statement = QueryBuilder::new(&table).delete(false);
+        assert_eq!(
+            statement.sql,
+            "DELETE FROM \"public\".\"users\" RETURNING *"
+        );
+    }
+
+    #[test]
+    fn arrays_are_sent_as_literals() {
+        assert_eq!(
+            array_literal(&[json!(1), json!(null), json!("a\"b"), json!([2, 3])]),
+            "{1,NULL,\"a\\\"b\",{2,3}}"
+        );
+    }
+
+    #[test]
+    fn strings_stay_strings_in_json_columns() {
+        let sent = |ty: &Type| -> Vec<u8> {
+            let mut out: BytesMut = BytesMut::new();
+            SqlParam::Json(json!("text")).to_sql(ty, &mut out).unwrap();
+            out.to_vec()
+        };
+        assert_eq!(sent(&Type::TEXT), b"text");
+        assert_eq!(sent(&Type::JSON), b"\"text\"");
+        // Postgres sends jsonb back with a leading format version byte.
+        let stored: Vec<u8> = [&[1], sent(&Type::JSONB).as_slice()].concat();
+        assert_eq!(to_json(&Type::JSONB, &stored), json!("text"));
+    }
+}
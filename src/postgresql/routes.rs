@@ -1,10 +1,29 @@
 use actix_web::web;
 
-use super::postgres::{find_one, index};
-pub fn postgres_config(cfg: &mut web::ServiceConfig) {
+use crate::{commands::init::AppState, middleware::auth::RequireApiKey};
+
+use super::postgres::{
+    delete_many, delete_one, drop_table, find_many, find_one, get_all_databases, get_schemas,
+    get_tables, index, insert_many, insert_one, update_many, update_one,
+};
+pub fn postgres_config(cfg: &mut web::ServiceConfig, prefix: &str, client: web::Data<AppState>) {
     cfg.service(
-        web::scope("/postgres")
+        web::scope(prefix)
+            .app_data(client)
+            .wrap(RequireApiKey)
             .route("/", web::get().to(index))
-            .route("/find_one/{table}", web::get().to(find_one)),
+            .route("/find_one/{table}", web::get().to(find_one))
+            .route("/find_many/{table}", web::get().to(find_many))
+            .route("/insert_one/{table}", web::post().to(insert_one))
+            .route("/insert_many/{table}", web::post().to(insert_many))
+            .route("/delete_one/{table}", web::delete().to(delete_one))
+            .route("/delete_many/{table}", web::delete().to(delete_many))
+            .route("/update_one/{table}", web::put().to(update_one))
+            .route("/update_many/{table}", web::put().to(update_many))
+            .route("/get_all_databases", web::get().to(get_all_databases))
+            .route("/get_schemas", web::get().to(get_schemas))
+            .route("/get_tables", web::get().to(get_tables))
+            .route("/get_tables/{schema}", web::get().to(get_tables))
+            .route("/drop_table/{table}", web::delete().to(drop_table)),
     );
 }
@@ -0,0 +1,151 @@
+use crate::commands::init::DatabaseTlsConfig;
+use native_tls::{Certificate, Identity, TlsConnector};
+use postgres_native_tls::MakeTlsConnector;
+use std::{fs, io};
+use tokio_postgres::config::{Config, SslMode};
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+fn read(path: &str) -> io::Result<Vec<u8>> {
+    fs::read(path).map_err(|e| invalid(format!("Unable to read {path}: {e}")))
+}
+
+/// How much of the server certificate is checked, following libpq's `sslmode`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Verify {
+    /// `prefer` and `require`: encrypted, but any certificate is accepted
+    /// unless a root certificate is given.
+    Nothing,
+    /// `verify-ca`: the certificate must be signed by a trusted CA.
+    Ca,
+    /// `verify-full`: the CA and the host name are both checked.
+    Full,
+}
+
+/// TLS settings taken out of the connection string. tokio-postgres only
+/// understands `sslmode=disable|prefer|require`, so the libpq options it
+/// would reject are removed before the rest is parsed.
+#[derive(Debug, Default)]
+struct UriTls {
+    verify: Option<Verify>,
+    root_cert_path: Option<String>,
+    client_cert_path: Option<String>,
+    client_key_path: Option<String>,
+}
+
+impl UriTls {
+    /// Returns false for options that tokio-postgres should see.
+    fn take(&mut self, key: &str, value: &str) -> bool {
+        match (key, value) {
+            ("sslmode", "verify-ca") => self.verify = Some(Verify::Ca),
+            ("sslmode", "verify-full") => self.verify = Some(Verify::Full),
+            ("sslrootcert", _) => self.root_cert_path = Some(value.to_string()),
+            ("sslcert", _) => self.client_cert_path = Some(value.to_string()),
+            ("sslkey", _) => self.client_key_path = Some(value.to_string()),
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// Splits both `postgres://...?sslmode=...` and `host=... sslmode=...` forms.
+fn split_uri(uri: &str) -> (String, UriTls) {
+    let mut tls: UriTls = UriTls::default();
+    if uri.starts_with("postgres://") || uri.starts_with("postgresql://") {
+        let Some((base, query)) = uri.split_once('?') else {
+            return (uri.to_string(), tls);
+        };
+        let kept: Vec<&str> = query
+            .split('&')
+            .filter(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                !tls.take(key, value)
+            })
+            .collect();
+        let uri: String = match kept.is_empty() {
+            true => base.to_string(),
+            false => format!("{base}?{}", kept.join("&")),
+        };
+        (uri, tls)
+    } else {
+        let kept: Vec<&str> = uri
+            .split_whitespace()
+            .filter(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                !tls.take(key.trim(), value.trim_matches('\''))
+            })
+            .collect();
+        (kept.join(" "), tls)
+    }
+}
+
+/// Parses the connection string and builds the TLS connector for it. Paths
+/// given in the URI take precedence over `database_tls` in config.yaml.
+pub fn connect_config(
+    uri: &str,
+    settings: Option<&DatabaseTlsConfig>,
+) -> io::Result<(Config, MakeTlsConnector)> {
+    let (uri, tls) = split_uri(uri);
+    let mut config: Config = uri
+        .parse()
+        .map_err(|e| invalid(format!("Invalid Postgres uri: {e}")))?;
+    if tls.verify.is_some() {
+        config.ssl_mode(SslMode::Require);
+    }
+
+    let from_settings = |path: fn(&DatabaseTlsConfig) -> &Option<String>| {
+        settings.and_then(|settings| path(settings).clone())
+    };
+    let root_cert_path: Option<String> = tls
+        .root_cert_path
+        .or_else(|| from_settings(|settings| &settings.root_cert_path));
+    let client_cert_path: Option<String> = tls
+        .client_cert_path
+        .or_else(|| from_settings(|settings| &settings.client_cert_path));
+    let client_key_path: Option<String> = tls
+        .client_key_path
+        .or_else(|| from_settings(|settings| &settings.client_key_path));
+
+    // Like libpq, a root certificate upgrades `require` to `verify-ca`.
+    let verify: Verify = match (tls.verify, &root_cert_path) {
+        (Some(verify), _) => verify,
+        (None, Some(_)) => Verify::Ca,
+        (None, None) => Verify::Nothing,
+    };
+
+    let mut builder = TlsConnector::builder();
+    if let Some(path) = &root_cert_path {
+        let certificate: Certificate = Certificate::from_pem(&read(path)?)
+            .map_err(|e| invalid(format!("Invalid root certificate in {path}: {e}")))?;
+        // Only the given root is trusted, as with libpq's `sslrootcert`.
+        builder
+            .add_root_certificate(certificate)
+            .disable_built_in_roots(true);
+    }
+    match (&client_cert_path, &client_key_path) {
+        (Some(cert_path), Some(key_path)) => {
+            let identity: Identity = Identity::from_pkcs8(&read(cert_path)?, &read(key_path)?)
+                .map_err(|e| {
+                    invalid(format!(
+                        "Invalid client certificate or key, the key must be PKCS#8: {e}"
+                    ))
+                })?;
+            builder.identity(identity);
+        }
+        (None, None) => {}
+        _ => {
+            return Err(invalid(String::from(
+                "A client certificate needs both a certificate and a key path",
+            )))
+        }
+    }
+    builder
+        .danger_accept_invalid_certs(verify == Verify::Nothing)
+        .danger_accept_invalid_hostnames(verify != Verify::Full);
+    let connector: TlsConnector = builder
+        .build()
+        .map_err(|e| invalid(format!("Unable to set up Postgres TLS: {e}")))?;
+    Ok((config, MakeTlsConnector::new(connector)))
+}
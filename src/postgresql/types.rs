@@ -0,0 +1,543 @@
+use std::error::Error;
+
+use chrono::NaiveTime;
+use fallible_iterator::FallibleIterator;
+use postgres_protocol::types as protocol;
+use serde_json::{json, Map, Value};
+use tokio_postgres::{
+    types::{FromSql, Kind, Type},
+    Row,
+};
+
+type DecodeError = Box<dyn Error + Sync + Send>;
+
+/// The undecoded binary value of a column, accepted for every type so the
+/// conversion below can pick the decoding from the column type itself.
+struct RawValue<'a>(Option<&'a [u8]>);
+
+impl<'a> FromSql<'a> for RawValue<'a> {
+    fn from_sql(_ty: &Type, raw: &'a [u8]) -> Result<Self, DecodeError> {
+        Ok(RawValue(Some(raw)))
+    }
+
+    fn from_sql_null(_ty: &Type) -> Result<Self, DecodeError> {
+        Ok(RawValue(None))
+    }
+
+    fn accepts(_ty: &Type) -> bool {
+        true
+    }
+}
+
+pub fn row_to_json(row: &Row) -> Value {
+    let mut object: Map<String, Value> = Map::new();
+    for (index, column) in row.columns().iter().enumerate() {
+        let value: Value = match row.try_get::<_, RawValue>(index) {
+            Ok(RawValue(Some(raw))) => to_json(column.type_(), raw),
+            Ok(RawValue(None)) | Err(_) => Value::Null,
+        };
+        object.insert(column.name().to_string(), value);
+    }
+    Value::Object(object)
+}
+
+pub fn rows_to_json(rows: &[Row]) -> Vec<Value> {
+    rows.iter().map(row_to_json).collect()
+}
+
+/// Converts a binary encoded value of type `ty` to JSON. Values that cannot be
+/// decoded are returned as hex.
+pub fn to_json(ty: &Type, raw: &[u8]) -> Value {
+    decode(ty, raw).unwrap_or_else(|_| Value::String(hex(raw)))
+}
+
+fn hex(raw: &[u8]) -> String {
+    let digits: String = raw.iter().map(|byte| format!("{byte:02x}")).collect();
+    format!("\\x{digits}")
+}
+
+fn decode(ty: &Type, raw: &[u8]) -> Result<Value, DecodeError> {
+    let value: Value = match *ty {
+        Type::BOOL => json!(protocol::bool_from_sql(raw)?),
+        Type::INT2 => json!(protocol::int2_from_sql(raw)?),
+        Type::INT4 => json!(protocol::int4_from_sql(raw)?),
+        Type::INT8 => json!(protocol::int8_from_sql(raw)?),
+        Type::OID => json!(protocol::oid_from_sql(raw)?),
+        Type::FLOAT4 => float_to_json(protocol::float4_from_sql(raw)? as f64),
+        Type::FLOAT8 => float_to_json(protocol::float8_from_sql(raw)?),
+        Type::NUMERIC => numeric_to_json(raw)?,
+        Type::MONEY => {
+            let cents: i64 = protocol::int8_from_sql(raw)?;
+            let sign: &str = if cents < 0 { "-" } else { "" };
+            Value::String(format!(
+                "{sign}{}.{:02}",
+                (cents / 100).abs(),
+                (cents % 100).abs()
+            ))
+        }
+        Type::CHAR => Value::String((protocol::char_from_sql(raw)? as u8 as char).to_string()),
+        Type::TEXT | Type::VARCHAR | Type::BPCHAR | Type::NAME | Type::UNKNOWN | Type::XML => {
+            Value::String(protocol::text_from_sql(raw)?.to_string())
+        }
+        Type::BYTEA => Value::String(hex(protocol::bytea_from_sql(raw))),
+        Type::UUID => Value::String(uuid_to_string(&protocol::uuid_from_sql(raw)?)),
+        Type::JSON => serde_json::from_slice(raw)?,
+        // jsonb is prefixed with a one byte format version.
+        Type::JSONB => match raw.split_first() {
+            Some((1, json)) => serde_json::from_slice(json)?,
+            _ => return Err("unsupported jsonb version".into()),
+        },
+        Type::DATE => date_to_json(protocol::date_from_sql(raw)?),
+        Type::TIME => Value::String(time_to_string(protocol::time_from_sql(raw)?)?),
+        Type::TIMETZ => timetz_to_json(raw)?,
+        Type::TIMESTAMP => timestamp_to_json(protocol::timestamp_from_sql(raw)?, false)?,
+        Type::TIMESTAMPTZ => timestamp_to_json(protocol::timestamp_from_sql(raw)?, true)?,
+        Type::INTERVAL => interval_to_json(raw)?,
+        Type::INET | Type::CIDR => {
+            let inet: protocol::Inet = protocol::inet_from_sql(raw)?;
+            Value::String(format!("{}/{}", inet.addr(), inet.netmask()))
+        }
+        Type::MACADDR => {
+            let bytes: [u8; 6] = protocol::macaddr_from_sql(raw)?;
+            let parts: Vec<String> = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+            Value::String(parts.join(":"))
+        }
+        _ => match ty.kind() {
+            Kind::Array(member) => array_to_json(member, raw)?,
+            Kind::Domain(inner) => decode(inner, raw)?,
+            Kind::Composite(fields) => composite_to_json(fields, raw)?,
+            // Enum labels, and most extension types like citext, are sent as text.
+            _ => Value::String(protocol::text_from_sql(raw)?.to_string()),
+        },
+    };
+    Ok(value)
+}
+
+fn float_to_json(value: f64) -> Value {
+    if value.is_nan() {
+        Value::String(String::from("NaN"))
+    } else if value.is_infinite() {
+        Value::String(String::from(if value > 0.0 {
+            "Infinity"
+        } else {
+            "-Infinity"
+        }))
+    } else {
+        json!(value)
+    }
+}
+
+fn read_i16(raw: &[u8], offset: usize) -> Result<i16, DecodeError> {
+    let bytes: [u8; 2] = raw
+        .get(offset..offset + 2)
+        .ok_or("invalid buffer size")?
+        .try_into()?;
+    Ok(i16::from_be_bytes(bytes))
+}
+
+fn read_i32(raw: &[u8], offset: usize) -> Result<i32, DecodeError> {
+    let bytes: [u8; 4] = raw
+        .get(offset..offset + 4)
+        .ok_or("invalid buffer size")?
+        .try_into()?;
+    Ok(i32::from_be_bytes(bytes))
+}
+
+fn read_i64(raw: &[u8], offset: usize) -> Result<i64, DecodeError> {
+    let bytes: [u8; 8] = raw
+        .get(offset..offset + 8)
+        .ok_or("invalid buffer size")?
+        .try_into()?;
+    Ok(i64::from_be_bytes(bytes))
+}
+
+/// Renders a binary `numeric` (base 10000 digits) as its decimal text.
+fn numeric_to_string(raw: &[u8]) -> Result<String, DecodeError> {
+    let ndigits: i16 = read_i16(raw, 0)?;
+    let weight: i16 = read_i16(raw, 2)?;
+    let sign: u16 = read_i16(raw, 4)? as u16;
+    let dscale: i16 = read_i16(raw, 6)?;
+    match sign {
+        0xC000 => return Ok(String::from("NaN")),
+        0xD000 => return Ok(String::from("Infinity")),
+        0xF000 => return Ok(String::from("-Infinity")),
+        _ => {}
+    }
+    let digits: Vec<i16> = (0..ndigits as usize)
+        .map(|i| read_i16(raw, 8 + i * 2))
+        .collect::<Result<_, _>>()?;
+    let digit = |index: i32| -> i16 {
+        if index >= 0 && (index as usize) < digits.len() {
+            digits[index as usize]
+        } else {
+            0
+        }
+    };
+    let mut text: String = String::new();
+    if sign == 0x4000 {
+        text.push('-');
+    }
+    if weight < 0 {
+        text.push('0');
+    } else {
+        for index in 0..=weight as i32 {
+            if index == 0 {
+                text.push_str(&digit(index).to_string());
+            } else {
+                text.push_str(&format!("{:04}", digit(index)));
+            }
+        }
+    }
+    if dscale > 0 {
+        let mut fraction: String = String::new();
+        let mut index: i32 = weight as i32 + 1;
+        while fraction.len() < dscale as usize {
+            fraction.push_str(&format!("{:04}", digit(index)));
+            index += 1;
+        }
+        fraction.truncate(dscale as usize);
+        text.push('.');
+        text.push_str(&fraction);
+    }
+    Ok(text)
+}
+
+// Numerics stay JSON numbers as long as an f64 holds them exactly, otherwise
+// they are returned as strings so no precision is lost.
+fn numeric_to_json(raw: &[u8]) -> Result<Value, DecodeError> {
+    let text: String = numeric_to_string(raw)?;
+    if let Ok(integer) = text.parse::<i64>() {
+        return Ok(json!(integer));
+    }
+    let normalized: &str = if text.contains('.') {
+        text.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        &text
+    };
+    match normalized.parse::<f64>() {
+        Ok(float) if float.is_finite() && float.to_string() == normalized => Ok(json!(float)),
+        _ => Ok(Value::String(text)),
+    }
+}
+
+fn uuid_to_string(bytes: &[u8; 16]) -> String {
+    let hex: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+// Postgres counts dates and timestamps from 2000-01-01, which is this many
+// days after 1970-01-01.
+const POSTGRES_EPOCH_DAYS: i64 = 10_957;
+
+const MICROS_PER_DAY: i64 = 86_400_000_000;
+
+/// The proleptic Gregorian `(year, month, day)` of a day counted from
+/// 1970-01-01. Postgres dates reach years chrono cannot represent, so the
+/// calendar is computed here, following
+/// https://howardhinnant.github.io/date_algorithms.html#civil_from_days.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let shifted: i64 = days + 719_468;
+    let era: i64 = shifted.div_euclid(146_097);
+    let day_of_era: i64 = shifted.rem_euclid(146_097);
+    let year_of_era: i64 =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year: i64 = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    // Months are counted from March so that February comes last.
+    let month_index: i64 = (5 * day_of_year + 2) / 153;
+    let day: i64 = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month: i64 = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year: i64 = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// ISO 8601 date of a day counted from 2000-01-01. Years outside 0000-9999
+/// carry a sign, e.g. `+294276-12-31`.
+fn date_string(days: i64) -> String {
+    let (year, month, day) = civil_from_days(days + POSTGRES_EPOCH_DAYS);
+    if (0..=9999).contains(&year) {
+        format!("{year:04}-{month:02}-{day:02}")
+    } else {
+        format!("{year:+05}-{month:02}-{day:02}")
+    }
+}
+
+fn date_to_json(days: i32) -> Value {
+    match days {
+        i32::MAX => Value::String(String::from("infinity")),
+        i32::MIN => Value::String(String::from("-infinity")),
+        _ => Value::String(date_string(days as i64)),
+    }
+}
+
+fn time_to_string(micros: i64) -> Result<String, DecodeError> {
+    let seconds: u32 = u32::try_from(micros / 1_000_000)?;
+    let nanos: u32 = u32::try_from(micros % 1_000_000)? * 1000;
+    let time: NaiveTime =
+        NaiveTime::from_num_seconds_from_midnight_opt(seconds, nanos).ok_or("time out of range")?;
+    Ok(time.format("%H:%M:%S%.f").to_string())
+}
+
+fn timetz_to_json(raw: &[u8]) -> Result<Value, DecodeError> {
+    let micros: i64 = read_i64(raw, 0)?;
+    // The zone is stored as seconds west of UTC.
+    let offset: i32 = -read_i32(raw, 8)?;
+    let sign: char = if offset < 0 { '-' } else { '+' };
+    let offset: i32 = offset.abs();
+    Ok(Value::String(format!(
+        "{}{sign}{:02}:{:02}",
+        time_to_string(micros)?,
+        offset / 3600,
+        offset % 3600 / 60
+    )))
+}
+
+fn timestamp_to_json(micros: i64, with_time_zone: bool) -> Result<Value, DecodeError> {
+    match micros {
+        i64::MAX => Ok(Value::String(String::from("infinity"))),
+        i64::MIN => Ok(Value::String(String::from("-infinity"))),
+        _ => {
+            let text: String = format!(
+                "{}T{}",
+                date_string(micros.div_euclid(MICROS_PER_DAY)),
+                time_to_string(micros.rem_euclid(MICROS_PER_DAY))?
+            );
+            if with_time_zone {
+                Ok(Value::String(format!("{text}Z")))
+            } else {
+                Ok(Value::String(text))
+            }
+        }
+    }
+}
+
+/// Intervals are rendered as ISO 8601 durations, e.g. `P1M2DT3H`.
+fn interval_to_json(raw: &[u8]) -> Result<Value, DecodeError> {
+    let micros: i64 = read_i64(raw, 0)?;
+    let days: i32 = read_i32(raw, 8)?;
+    let months: i32 = read_i32(raw, 12)?;
+    let mut text: String = String::from("P");
+    if months / 12 != 0 {
+        text.push_str(&format!("{}Y", months / 12));
+    }
+    if months % 12 != 0 {
+        text.push_str(&format!("{}M", months % 12));
+    }
+    if days != 0 {
+        text.push_str(&format!("{days}D"));
+    }
+    if micros != 0 {
+        text.push('T');
+        let hours: i64 = micros / 3_600_000_000;
+        let minutes: i64 = micros % 3_600_000_000 / 60_000_000;
+        let seconds: i64 = micros % 60_000_000 / 1_000_000;
+        let fraction: i64 = micros % 1_000_000;
+        if hours != 0 {
+            text.push_str(&format!("{hours}H"));
+        }
+        if minutes != 0 {
+            text.push_str(&format!("{minutes}M"));
+        }
+        if seconds != 0 || fraction != 0 {
+            if fraction != 0 {
+                let fraction: String = format!("{:06}", fraction.abs());
+                let sign: &str = if micros < 0 && seconds == 0 { "-" } else { "" };
+                text.push_str(&format!(
+                    "{sign}{seconds}.{}S",
+                    fraction.trim_end_matches('0')
+                ));
+            } else {
+                text.push_str(&format!("{seconds}S"));
+            }
+        }
+    }
+    if text == "P" {
+        text.push_str("T0S");
+    }
+    Ok(Value::String(text))
+}
+
+fn array_to_json(member: &Type, raw: &[u8]) -> Result<Value, DecodeError> {
+    let array: protocol::Array = protocol::array_from_sql(raw)?;
+    let dimensions: Vec<usize> = array
+        .dimensions()
+        .map(|dimension| Ok(dimension.len as usize))
+        .collect()?;
+    let values: Vec<Value> = array
+        .values()
+        .map(|value| {
+            Ok(match value {
+                Some(raw) => to_json(member, raw),
+                None => Value::Null,
+            })
+        })
+        .collect()?;
+    if dimensions.is_empty() {
+        return Ok(Value::Array(Vec::new()));
+    }
+    Ok(nest(&dimensions, &mut values.into_iter()))
+}
+
+// Arrays arrive flattened in row-major order, one dimension at a time.
+fn nest(dimensions: &[usize], values: &mut std::vec::IntoIter<Value>) -> Value {
+    let (length, inner) = dimensions
+        .split_first()
+        .expect("dimensions are never empty here");
+    let elements: Vec<Value> = (0..*length)
+        .map(|_| {
+            if inner.is_empty() {
+                values.next().unwrap_or(Value::Null)
+            } else {
+                nest(inner, values)
+            }
+        })
+        .collect();
+    Value::Array(elements)
+}
+
+fn composite_to_json(
+    fields: &[tokio_postgres::types::Field],
+    raw: &[u8],
+) -> Result<Value, DecodeError> {
+    let count: i32 = read_i32(raw, 0)?;
+    let mut offset: usize = 4;
+    let mut object: Map<String, Value> = Map::new();
+    for field in fields.iter().take(count.max(0) as usize) {
+        // Each field carries its own type oid before the length and value.
+        let length: i32 = read_i32(raw, offset + 4)?;
+        offset += 8;
+        let value: Value = if length < 0 {
+            Value::Null
+        } else {
+            let end: usize = offset + length as usize;
+            let value: &[u8] = raw.get(offset..end).ok_or("invalid buffer size")?;
+            offset = end;
+            to_json(field.type_(), value)
+        };
+        object.insert(field.name().to_string(), value);
+    }
+    Ok(Value::Object(object))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numeric(weight: i16, sign: u16, dscale: i16, digits: &[i16]) -> Vec<u8> {
+        let mut raw: Vec<u8> = Vec::new();
+        for value in [digits.len() as i16, weight, sign as i16, dscale] {
+            raw.extend_from_slice(&value.to_be_bytes());
+        }
+        for digit in digits {
+            raw.extend_from_slice(&digit.to_be_bytes());
+        }
+        raw
+    }
+
+    fn interval(micros: i64, days: i32, months: i32) -> Vec<u8> {
+        let mut raw: Vec<u8> = micros.to_be_bytes().to_vec();
+        raw.extend_from_slice(&days.to_be_bytes());
+        raw.extend_from_slice(&months.to_be_bytes());
+        raw
+    }
+
+    #[test]
+    fn numerics_keep_their_scale() {
+        let cases: [(Vec<u8>, &str); 7] = [
+            (numeric(0, 0, 0, &[]), "0"),
+            (numeric(1, 0, 0, &[12, 3456]), "123456"),
+            (numeric(0, 0x4000, 2, &[1, 2500]), "-1.25"),
+            (numeric(-1, 0, 4, &[5]), "0.0005"),
+            (numeric(1, 0, 0, &[1]), "10000"),
+            (numeric(0, 0, 3, &[7]), "7.000"),
+            (numeric(0, 0xC000, 0, &[]), "NaN"),
+        ];
+        for (raw, expected) in cases {
+            assert_eq!(numeric_to_string(&raw).unwrap(), expected);
+        }
+        assert!(numeric_to_string(&[0, 1]).is_err());
+    }
+
+    #[test]
+    fn large_numerics_become_strings() {
+        let raw: Vec<u8> = numeric(5, 0, 0, &[1, 0, 0, 0, 0, 1]);
+        assert_eq!(
+            numeric_to_json(&raw).unwrap(),
+            json!("100000000000000000001")
+        );
+        assert_eq!(
+            numeric_to_json(&numeric(0, 0, 2, &[1, 5000])).unwrap(),
+            json!(1.5)
+        );
+    }
+
+    #[test]
+    fn intervals_are_iso_durations() {
+        let cases: [(Vec<u8>, &str); 6] = [
+            (interval(0, 0, 0), "PT0S"),
+            (interval(0, 2, 14), "P1Y2M2D"),
+            (interval(3 * 3_600_000_000 + 4 * 60_000_000, 0, 0), "PT3H4M"),
+            (interval(1_500_000, 0, 0), "PT1.5S"),
+            (interval(-500_000, 0, 0), "PT-0.5S"),
+            (interval(-90_000_000, -1, 0), "P-1DT-1M-30S"),
+        ];
+        for (raw, expected) in cases {
+            assert_eq!(interval_to_json(&raw).unwrap(), json!(expected));
+        }
+    }
+
+    #[test]
+    fn dates_outside_chrono_are_decoded() {
+        // Expected values are Postgres's own `'2000-01-01'::date + days`.
+        let cases: [(i32, &str); 7] = [
+            (0, "2000-01-01"),
+            (-1, "1999-12-31"),
+            (59, "2000-02-29"),
+            (-2_451_507, "-4712-01-01"),
+            (72_318_074, "+200000-02-29"),
+            (538_976_288, "+1477666-12-15"),
+            (2_145_031_948, "+5874897-12-31"),
+        ];
+        for (days, expected) in cases {
+            assert_eq!(to_json(&Type::DATE, &days.to_be_bytes()), json!(expected));
+        }
+        assert_eq!(date_to_json(i32::MAX), json!("infinity"));
+    }
+
+    #[test]
+    fn timestamps_outside_chrono_are_decoded() {
+        assert_eq!(
+            timestamp_to_json(1_500_000, true).unwrap(),
+            json!("2000-01-01T00:00:01.500Z")
+        );
+        assert_eq!(
+            timestamp_to_json(-1, false).unwrap(),
+            json!("1999-12-31T23:59:59.999999")
+        );
+        assert_eq!(
+            timestamp_to_json(i64::MIN, false).unwrap(),
+            json!("-infinity")
+        );
+        let micros: i64 = 106_751_982 * MICROS_PER_DAY + MICROS_PER_DAY - 1;
+        assert_eq!(
+            to_json(&Type::TIMESTAMPTZ, &micros.to_be_bytes()),
+            json!("+294276-12-31T23:59:59.999999Z")
+        );
+        assert!(time_to_string(-1).is_err());
+    }
+
+    #[test]
+    fn undecodable_values_are_hex() {
+        assert_eq!(to_json(&Type::DATE, b"AAA"), json!("\\x414141"));
+    }
+}
@@ -0,0 +1,49 @@
+use crate::error::ApiError;
+use actix_web::{http::header, web::Bytes, HttpRequest, HttpResponse};
+use futures::{stream, Stream, StreamExt};
+use serde::Serialize;
+use std::pin::Pin;
+
+pub const NDJSON: &str = "application/x-ndjson";
+
+/// True when the client asked for newline-delimited JSON with
+/// `Accept: application/x-ndjson`.
+pub fn wants_ndjson(req: &HttpRequest) -> bool {
+    req.headers()
+        .get_all(header::ACCEPT)
+        .filter_map(|value| value.to_str().ok())
+        .any(|value| {
+            value
+                .split(',')
+                .any(|media| media.trim().starts_with(NDJSON))
+        })
+}
+
+fn line<T: Serialize>(item: &T) -> Bytes {
+    let mut bytes: Vec<u8> = serde_json::to_vec(item).unwrap_or_default();
+    bytes.push(b'\n');
+    Bytes::from(bytes)
+}
+
+/// Streams `items` as one JSON document per line. The next item is only
+/// pulled once the previous one has been written, so a slow client slows
+/// down the database cursor instead of filling memory.
+///
+/// The status line is already sent when an item fails, so the error is
+/// written as a final `{"error", "code", "details"}` line and the stream ends.
+pub fn ndjson<S, T, E>(items: S) -> HttpResponse
+where
+    S: Stream<Item = Result<T, E>> + 'static,
+    T: Serialize,
+    E: Into<ApiError>,
+{
+    let items: Pin<Box<S>> = Box::pin(items);
+    let lines = stream::unfold(Some(items), |items| async move {
+        let mut items: Pin<Box<S>> = items?;
+        match items.next().await? {
+            Ok(item) => Some((Ok::<Bytes, actix_web::Error>(line(&item)), Some(items))),
+            Err(e) => Some((Ok(line(&e.into().body())), None)),
+        }
+    });
+    HttpResponse::Ok().content_type(NDJSON).streaming(lines)
+}
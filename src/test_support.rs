@@ -0,0 +1,44 @@
+//! Helpers shared by the unit tests.
+
+use crate::{
+    commands::init::ConfigLayout,
+    middleware::auth::{ApiKey, RequireApiKey},
+    mongo::expose::Expose,
+};
+use actix_web::{
+    http::StatusCode,
+    test::{call_service, init_service, TestRequest},
+    web, App, HttpResponse,
+};
+use std::collections::HashMap;
+
+/// The parsed query string of a request, as handlers receive it.
+pub fn query_string(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+    pairs
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+/// Sends each uri with `key` to a Mongo `find_one` route behind
+/// `RequireApiKey`, set up from `config`, and checks the status it answers.
+pub async fn assert_statuses(config: &str, key: &str, cases: &[(&str, StatusCode)]) {
+    let config: ConfigLayout = serde_yaml::from_str(config).unwrap();
+    let app = init_service(
+        App::new()
+            .app_data(web::Data::new(ApiKey::from_config(&config).unwrap()))
+            .app_data(web::Data::new(Expose::from_config(&config).unwrap()))
+            .service(web::scope("/mongodb").wrap(RequireApiKey).route(
+                "/find_one/{database}/{collection}",
+                web::get().to(HttpResponse::Ok),
+            )),
+    )
+    .await;
+    for (uri, status) in cases {
+        let req = TestRequest::get()
+            .uri(uri)
+            .insert_header(("x-api-key", key))
+            .to_request();
+        assert_eq!(call_service(&app, req).await.status(), *status, "{uri}");
+    }
+}
@@ -0,0 +1,69 @@
+use crate::commands::init::TlsConfig;
+use rustls::{
+    server::AllowAnyAuthenticatedClient, Certificate, PrivateKey, RootCertStore, ServerConfig,
+};
+use rustls_pemfile::Item;
+use std::{fs::File, io, io::BufReader, sync::Arc};
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+fn open(path: &str) -> io::Result<BufReader<File>> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| invalid(format!("Unable to open {path}: {e}")))
+}
+
+pub fn load_certificates(path: &str) -> io::Result<Vec<Certificate>> {
+    let certificates: Vec<Certificate> = rustls_pemfile::certs(&mut open(path)?)?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    if certificates.is_empty() {
+        return Err(invalid(format!("No certificate found in {path}")));
+    }
+    Ok(certificates)
+}
+
+/// Reads the first PKCS#8, PKCS#1 (RSA) or SEC1 (EC) key of a PEM file.
+pub fn load_private_key(path: &str) -> io::Result<PrivateKey> {
+    let mut reader: BufReader<File> = open(path)?;
+    while let Some(item) = rustls_pemfile::read_one(&mut reader)? {
+        match item {
+            Item::PKCS8Key(key) | Item::RSAKey(key) | Item::ECKey(key) => {
+                return Ok(PrivateKey(key))
+            }
+            _ => continue,
+        }
+    }
+    Err(invalid(format!("No private key found in {path}")))
+}
+
+pub fn load_roots(path: &str) -> io::Result<RootCertStore> {
+    let mut roots: RootCertStore = RootCertStore::empty();
+    for certificate in load_certificates(path)? {
+        roots
+            .add(&certificate)
+            .map_err(|e| invalid(format!("Invalid CA certificate in {path}: {e}")))?;
+    }
+    Ok(roots)
+}
+
+/// Builds the HTTPS configuration. With `client_ca_path` set, clients must
+/// present a certificate signed by that CA.
+pub fn server_config(tls: &TlsConfig) -> io::Result<ServerConfig> {
+    let builder = ServerConfig::builder().with_safe_defaults();
+    let builder = match &tls.client_ca_path {
+        Some(path) => builder.with_client_cert_verifier(Arc::new(
+            AllowAnyAuthenticatedClient::new(load_roots(path)?),
+        )),
+        None => builder.with_no_client_auth(),
+    };
+    builder
+        .with_single_cert(
+            load_certificates(&tls.cert_path)?,
+            load_private_key(&tls.key_path)?,
+        )
+        .map_err(|e| invalid(format!("Invalid TLS certificate or key: {e}")))
+}